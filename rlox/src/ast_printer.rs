@@ -0,0 +1,371 @@
+use std::rc::Rc;
+
+use crate::{
+    expr::{Expr, ExprId, ExprVisitor},
+    pattern::Pattern,
+    stmt::{FunctionDecl, MatchArm, Stmt, StmtVisitor, TypeAnnotation},
+    token::{Token, TokenLiteral},
+};
+
+/// Renders a program as parenthesized prefix notation, one statement per line and nested
+/// statements indented below the one containing them.
+pub struct AstPrinter {
+    depth: usize,
+}
+
+impl AstPrinter {
+    pub fn new() -> Self {
+        Self { depth: 0 }
+    }
+
+    pub fn print(&mut self, statements: &[Option<Stmt>]) -> String {
+        let mut out = String::new();
+        for statement in statements.iter().flatten() {
+            out.push_str(&statement.accept_visitor(self));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Renders `statements` on their own lines, one level deeper than the current one.
+    fn nested<'a>(&mut self, statements: impl IntoIterator<Item = &'a Stmt>) -> String {
+        self.depth += 1;
+        let mut out = String::new();
+        for statement in statements {
+            out.push('\n');
+            out.push_str(&"  ".repeat(self.depth));
+            out.push_str(&statement.accept_visitor(self));
+        }
+        self.depth -= 1;
+        out
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> String {
+        match pattern {
+            Pattern::Wildcard(_) => "_".into(),
+            Pattern::Literal { token, value } => self.visit_literal(Some(token), value),
+            Pattern::Binding(name) => name.lexeme.clone(),
+            Pattern::Alternatives(alternatives) => {
+                let alternatives: Vec<String> = alternatives
+                    .iter()
+                    .map(|alternative| self.pattern(alternative))
+                    .collect();
+                alternatives.join(" | ")
+            }
+            Pattern::Instance { class, fields } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, pattern)| format!("{}: {}", name.lexeme, self.pattern(pattern)))
+                    .collect();
+                format!("{}({})", class.accept_visitor(self), fields.join(", "))
+            }
+        }
+    }
+
+    /// A declared name followed by its type annotation, if it has one.
+    fn typed(name: &Token, annotation: Option<&TypeAnnotation>) -> String {
+        match annotation {
+            Some(annotation) => format!("{}: {}", name.lexeme, annotation.name.lexeme),
+            None => name.lexeme.clone(),
+        }
+    }
+
+    fn parenthesize<'a>(
+        &mut self,
+        name: &str,
+        exprs: impl IntoIterator<Item = &'a Expr>,
+    ) -> String {
+        let mut out = format!("({}", name);
+        for expr in exprs {
+            out.push(' ');
+            out.push_str(&expr.accept_visitor(self));
+        }
+        out.push(')');
+        out
+    }
+}
+
+impl Default for AstPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StmtVisitor for AstPrinter {
+    type Output = String;
+
+    fn visit_block(&mut self, statements: &Vec<Option<Stmt>>) -> Self::Output {
+        format!("(block{})", self.nested(statements.iter().flatten()))
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        traits: &[Expr],
+        methods: &Vec<Stmt>,
+    ) -> Self::Output {
+        let superclass = match superclass {
+            Some(superclass) => format!(" < {}", superclass.accept_visitor(self)),
+            None => String::new(),
+        };
+        let traits: Vec<String> = traits
+            .iter()
+            .map(|used| used.accept_visitor(self))
+            .collect();
+        let traits = if traits.is_empty() {
+            String::new()
+        } else {
+            format!(" with {}", traits.join(" "))
+        };
+        format!(
+            "(class {}{}{}{})",
+            name.lexeme,
+            superclass,
+            traits,
+            self.nested(methods)
+        )
+    }
+
+    fn visit_const(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output {
+        self.parenthesize(
+            &format!("const {}", Self::typed(name, annotation)),
+            [initializer],
+        )
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) -> Self::Output {
+        self.parenthesize(";", [expression])
+    }
+
+    fn visit_for_in(
+        &mut self,
+        _keyword: &Token,
+        name: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        let iterable = iterable.accept_visitor(self);
+        format!(
+            "(for {} in {}{})",
+            name.lexeme,
+            iterable,
+            self.nested([body])
+        )
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output {
+        let mut params: Vec<String> = Vec::new();
+        for param in &declaration.params {
+            let name = Self::typed(&param.name, param.annotation.as_ref());
+            params.push(match &param.default {
+                Some(default) => format!("{}={}", name, default.accept_visitor(self)),
+                None => name,
+            });
+        }
+        if let Some(rest) = &declaration.rest {
+            params.push(format!("...{}", rest.lexeme));
+        }
+        let return_type = match &declaration.return_type {
+            Some(annotation) => format!(": {}", annotation.name.lexeme),
+            None => String::new(),
+        };
+        format!(
+            "(fun {}({}){}{})",
+            declaration.name.lexeme,
+            params.join(" "),
+            return_type,
+            self.nested(declaration.body.iter().flatten())
+        )
+    }
+
+    fn visit_if(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Self::Output {
+        let condition = condition.accept_visitor(self);
+        let branches = self.nested([then_branch].into_iter().chain(else_branch));
+        format!("(if {}{})", condition, branches)
+    }
+
+    fn visit_match(&mut self, _keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output {
+        let mut out = format!("(match {}", subject.accept_visitor(self));
+        self.depth += 1;
+        for arm in arms {
+            let guard = match &arm.guard {
+                Some(guard) => format!(" if {}", guard.accept_visitor(self)),
+                None => String::new(),
+            };
+            out.push('\n');
+            out.push_str(&"  ".repeat(self.depth));
+            let pattern = self.pattern(&arm.pattern);
+            let body = self.nested([&arm.body]);
+            out.push_str(&format!("(case {}{}{})", pattern, guard, body));
+        }
+        self.depth -= 1;
+        out.push(')');
+        out
+    }
+
+    fn visit_print(&mut self, _keyword: &Token, expression: &Expr) -> Self::Output {
+        self.parenthesize("print", [expression])
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Option<Rc<Expr>>) -> Self::Output {
+        self.parenthesize("return", value.iter().map(|value| value.as_ref()))
+    }
+
+    fn visit_trait(&mut self, name: &Token, methods: &[Stmt]) -> Self::Output {
+        format!("(trait {}{})", name.lexeme, self.nested(methods))
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output {
+        self.parenthesize(
+            &format!("var {}", Self::typed(name, annotation)),
+            initializer,
+        )
+    }
+
+    fn visit_var_tuple(&mut self, names: &[Token], initializer: &Expr) -> Self::Output {
+        let names: Vec<&str> = names.iter().map(|name| name.lexeme.as_str()).collect();
+        self.parenthesize(&format!("var ({})", names.join(" ")), [initializer])
+    }
+
+    fn visit_while(&mut self, _keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output {
+        let condition = condition.accept_visitor(self);
+        format!("(while {}{})", condition, self.nested([body]))
+    }
+}
+
+impl ExprVisitor for AstPrinter {
+    type Output = String;
+
+    fn visit_assign(
+        &mut self,
+        _id: ExprId,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        let operator = operator.map_or("", |operator| operator.lexeme.as_str());
+        self.parenthesize(&format!("{}= {}", operator, name.lexeme), [value])
+    }
+
+    fn visit_assign_tuple(&mut self, targets: &[(ExprId, Token)], value: &Expr) -> Self::Output {
+        let names: Vec<&str> = targets
+            .iter()
+            .map(|(_, name)| name.lexeme.as_str())
+            .collect();
+        self.parenthesize(&format!("= ({})", names.join(" ")), [value])
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        self.parenthesize(&operator.lexeme, [left, right])
+    }
+
+    fn visit_call(
+        &mut self,
+        _id: ExprId,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output {
+        let mut out = self.parenthesize("call", [callee].into_iter().chain(arguments));
+        out.pop();
+        for (name, argument) in keywords {
+            out.push_str(&format!(
+                " {}: {}",
+                name.lexeme,
+                argument.accept_visitor(self)
+            ));
+        }
+        out.push(')');
+        out
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        _question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        self.parenthesize("?:", [condition, then_branch, else_branch])
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> Self::Output {
+        format!("(. {} {})", object.accept_visitor(self), name.lexeme)
+    }
+
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        format!(
+            "({}= (. {} {}) {})",
+            operator.map_or("", |operator| operator.lexeme.as_str()),
+            object.accept_visitor(self),
+            name.lexeme,
+            value.accept_visitor(self)
+        )
+    }
+
+    fn visit_super(&mut self, _id: ExprId, _keyword: &Token, method: &Token) -> Self::Output {
+        format!("(super {})", method.lexeme)
+    }
+
+    fn visit_this(&mut self, _id: ExprId, _keyword: &Token) -> Self::Output {
+        "this".into()
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> Self::Output {
+        self.parenthesize("group", [expression])
+    }
+
+    fn visit_literal(&mut self, _token: Option<&Token>, value: &TokenLiteral) -> Self::Output {
+        match value {
+            TokenLiteral::String(s) => format!("\"{}\"", s),
+            // keep the decimal point, so floats are told apart from integers
+            TokenLiteral::Number(n) => format!("{:?}", n.0),
+            TokenLiteral::BigInt(n) => format!("{}n", n),
+            value => value.to_string(),
+        }
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        self.parenthesize(&operator.lexeme, [left, right])
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output {
+        self.parenthesize("tuple", elements)
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Self::Output {
+        self.parenthesize(&operator.lexeme, [right])
+    }
+
+    fn visit_variable(&mut self, _id: ExprId, name: &Token) -> Self::Output {
+        name.lexeme.clone()
+    }
+
+    fn visit_yield(&mut self, _keyword: &Token, value: Option<&Expr>) -> Self::Output {
+        self.parenthesize("yield", value)
+    }
+}
@@ -0,0 +1,440 @@
+use std::{collections::BTreeMap, fs, rc::Rc};
+
+use crate::{
+    expr::{Expr, ExprId, ExprVisitor},
+    hook::Hook,
+    interpreter::Interpreter,
+    stmt::{FunctionDecl, MatchArm, Stmt, StmtVisitor, TypeAnnotation},
+    token::{Token, TokenLiteral},
+};
+
+/// Execution counts for the two arms of one branch. See `Hook::on_branch`.
+struct BranchPoint {
+    line: usize,
+    arms: [u64; 2],
+}
+
+/// Hook that records which statements and branch arms of a script ran.
+///
+/// Before the program starts, the whole tree is walked so that code which never runs is
+/// still reported. Once it finishes, an lcov tracefile is written and a summary is
+/// printed to stderr.
+pub struct Coverage {
+    source_path: String,
+    lcov_path: String,
+    lines: BTreeMap<usize, u64>,
+    /// Branch points keyed by the source offset of the token that introduces them.
+    branches: BTreeMap<usize, BranchPoint>,
+}
+
+impl Coverage {
+    pub fn new(source_path: &str, lcov_path: &str, statements: &[Option<Stmt>]) -> Self {
+        let mut coverage = Self {
+            source_path: source_path.into(),
+            lcov_path: lcov_path.into(),
+            lines: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        };
+        coverage.instrument_stmts(statements);
+        coverage
+    }
+
+    fn instrument_stmts(&mut self, statements: &[Option<Stmt>]) {
+        for statement in statements.iter().flatten() {
+            self.instrument_stmt(statement);
+        }
+    }
+
+    fn instrument_stmt(&mut self, statement: &Stmt) {
+        if let Some(line) = statement.line() {
+            self.lines.entry(line).or_insert(0);
+        }
+        statement.accept_visitor(self);
+    }
+
+    fn instrument_expr(&mut self, expr: &Expr) {
+        expr.accept_visitor(self);
+    }
+
+    fn instrument_branch(&mut self, token: &Token) {
+        self.branches.entry(token.cursor).or_insert(BranchPoint {
+            line: token.line,
+            arms: [0, 0],
+        });
+    }
+
+    fn lcov(&self) -> String {
+        let mut out = format!("TN:\nSF:{}\n", self.source_path);
+
+        let mut block = 0;
+        let mut previous_line = 0;
+        for branch in self.branches.values() {
+            block = if branch.line == previous_line {
+                block + 1
+            } else {
+                0
+            };
+            previous_line = branch.line;
+            let reached = branch.arms.iter().any(|count| *count > 0);
+            for (arm, count) in branch.arms.iter().enumerate() {
+                let taken = if reached {
+                    count.to_string()
+                } else {
+                    "-".into()
+                };
+                out.push_str(&format!(
+                    "BRDA:{},{},{},{}\n",
+                    branch.line, block, arm, taken
+                ));
+            }
+        }
+        let (branches_hit, branches_found) = self.branch_totals();
+        out.push_str(&format!("BRF:{}\nBRH:{}\n", branches_found, branches_hit));
+
+        for (line, count) in &self.lines {
+            out.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        let (lines_hit, lines_found) = self.line_totals();
+        out.push_str(&format!("LF:{}\nLH:{}\n", lines_found, lines_hit));
+        out.push_str("end_of_record\n");
+        out
+    }
+
+    fn line_totals(&self) -> (usize, usize) {
+        let hit = self.lines.values().filter(|count| **count > 0).count();
+        (hit, self.lines.len())
+    }
+
+    fn branch_totals(&self) -> (usize, usize) {
+        let hit = self
+            .branches
+            .values()
+            .flat_map(|branch| branch.arms.iter())
+            .filter(|count| **count > 0)
+            .count();
+        (hit, self.branches.len() * 2)
+    }
+
+    fn summary(&self) -> String {
+        fn percent(hit: usize, found: usize) -> f64 {
+            if found == 0 {
+                100.0
+            } else {
+                hit as f64 * 100.0 / found as f64
+            }
+        }
+
+        let (lines_hit, lines_found) = self.line_totals();
+        let (branches_hit, branches_found) = self.branch_totals();
+        let mut out = format!(
+            "{}: lines {}/{} ({:.1}%), branches {}/{} ({:.1}%)",
+            self.source_path,
+            lines_hit,
+            lines_found,
+            percent(lines_hit, lines_found),
+            branches_hit,
+            branches_found,
+            percent(branches_hit, branches_found),
+        );
+
+        let missed: Vec<String> = self
+            .lines
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(line, _)| line.to_string())
+            .collect();
+        if !missed.is_empty() {
+            out.push_str(&format!("\n  lines not run: {}", missed.join(", ")));
+        }
+        out
+    }
+}
+
+impl Hook for Coverage {
+    fn on_statement(&mut self, _interpreter: &mut Interpreter, statement: &Stmt) {
+        if let Some(line) = statement.line() {
+            *self.lines.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    fn on_branch(&mut self, _interpreter: &mut Interpreter, token: &Token, arm: usize) {
+        if let Some(branch) = self.branches.get_mut(&token.cursor) {
+            branch.arms[arm] += 1;
+        }
+    }
+
+    fn on_finish(&mut self, _interpreter: &mut Interpreter) {
+        if let Err(e) = fs::write(&self.lcov_path, self.lcov()) {
+            eprintln!("Could not write coverage to '{}': {}", self.lcov_path, e);
+        }
+        eprintln!("{}", self.summary());
+    }
+}
+
+impl StmtVisitor for Coverage {
+    type Output = ();
+
+    fn visit_block(&mut self, statements: &Vec<Option<Stmt>>) -> Self::Output {
+        self.instrument_stmts(statements);
+    }
+
+    fn visit_class(
+        &mut self,
+        _name: &Token,
+        _superclass: &Option<Expr>,
+        _traits: &[Expr],
+        methods: &Vec<Stmt>,
+    ) -> Self::Output {
+        for method in methods {
+            self.instrument_stmt(method);
+        }
+    }
+
+    fn visit_const(
+        &mut self,
+        _name: &Token,
+        _annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output {
+        self.instrument_expr(initializer);
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) -> Self::Output {
+        self.instrument_expr(expression);
+    }
+
+    fn visit_for_in(
+        &mut self,
+        keyword: &Token,
+        _name: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        self.instrument_branch(keyword);
+        self.instrument_expr(iterable);
+        self.instrument_stmt(body);
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output {
+        for default in declaration.params.iter().flat_map(|param| &param.default) {
+            self.instrument_expr(default);
+        }
+        self.instrument_stmts(&declaration.body);
+    }
+
+    fn visit_if(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Self::Output {
+        self.instrument_branch(keyword);
+        self.instrument_expr(condition);
+        self.instrument_stmt(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.instrument_stmt(else_branch);
+        }
+    }
+
+    fn visit_match(&mut self, _keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output {
+        self.instrument_expr(subject);
+        for arm in arms {
+            self.instrument_branch(arm.pattern.token());
+            if let Some(guard) = &arm.guard {
+                self.instrument_expr(guard);
+            }
+            self.instrument_stmt(&arm.body);
+        }
+    }
+
+    fn visit_print(&mut self, _keyword: &Token, expression: &Expr) -> Self::Output {
+        self.instrument_expr(expression);
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Option<Rc<Expr>>) -> Self::Output {
+        if let Some(value) = value {
+            self.instrument_expr(value);
+        }
+    }
+
+    fn visit_trait(&mut self, _name: &Token, methods: &[Stmt]) -> Self::Output {
+        for method in methods {
+            self.instrument_stmt(method);
+        }
+    }
+
+    fn visit_var(
+        &mut self,
+        _name: &Token,
+        _annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output {
+        if let Some(initializer) = initializer {
+            self.instrument_expr(initializer);
+        }
+    }
+
+    fn visit_var_tuple(&mut self, _names: &[Token], initializer: &Expr) -> Self::Output {
+        self.instrument_expr(initializer);
+    }
+
+    fn visit_while(&mut self, keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output {
+        self.instrument_branch(keyword);
+        self.instrument_expr(condition);
+        self.instrument_stmt(body);
+    }
+}
+
+impl ExprVisitor for Coverage {
+    type Output = ();
+
+    fn visit_assign(
+        &mut self,
+        _id: ExprId,
+        _name: &Token,
+        _operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        self.instrument_expr(value);
+    }
+
+    fn visit_assign_tuple(&mut self, _targets: &[(ExprId, Token)], value: &Expr) -> Self::Output {
+        self.instrument_expr(value);
+    }
+
+    fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Self::Output {
+        self.instrument_expr(left);
+        self.instrument_expr(right);
+    }
+
+    fn visit_call(
+        &mut self,
+        _id: ExprId,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output {
+        self.instrument_expr(callee);
+        for argument in arguments {
+            self.instrument_expr(argument);
+        }
+        for (_, argument) in keywords {
+            self.instrument_expr(argument);
+        }
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        self.instrument_branch(question);
+        self.instrument_expr(condition);
+        self.instrument_expr(then_branch);
+        self.instrument_expr(else_branch);
+    }
+
+    fn visit_get(&mut self, object: &Expr, _name: &Token) -> Self::Output {
+        self.instrument_expr(object);
+    }
+
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        _name: &Token,
+        _operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        self.instrument_expr(object);
+        self.instrument_expr(value);
+    }
+
+    fn visit_super(&mut self, _id: ExprId, _keyword: &Token, _method: &Token) -> Self::Output {}
+
+    fn visit_this(&mut self, _id: ExprId, _keyword: &Token) -> Self::Output {}
+
+    fn visit_grouping(&mut self, expression: &Expr) -> Self::Output {
+        self.instrument_expr(expression);
+    }
+
+    fn visit_literal(&mut self, _token: Option<&Token>, _value: &TokenLiteral) -> Self::Output {}
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        self.instrument_branch(operator);
+        self.instrument_expr(left);
+        self.instrument_expr(right);
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output {
+        for element in elements {
+            self.instrument_expr(element);
+        }
+    }
+
+    fn visit_unary(&mut self, _operator: &Token, right: &Expr) -> Self::Output {
+        self.instrument_expr(right);
+    }
+
+    fn visit_variable(&mut self, _id: ExprId, _name: &Token) -> Self::Output {}
+
+    fn visit_yield(&mut self, _keyword: &Token, value: Option<&Expr>) -> Self::Output {
+        if let Some(value) = value {
+            self.instrument_expr(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::lox::{Lox, Options};
+
+    #[test]
+    fn test_lcov_lines_and_branches() {
+        let source = "fun sign(n) {
+  if (n < 0) {
+    return \"negative\";
+  }
+  return \"positive\";
+}
+sign(1);
+sign(2);
+";
+        let path = env::temp_dir().join(format!("rlox-coverage-{}.info", std::process::id()));
+        let options = Options {
+            coverage: Some(path.to_string_lossy().into()),
+            ..Options::default()
+        };
+        Lox::run(source, "sign.lox", &options);
+        let lcov = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let records: Vec<&str> = lcov.lines().collect();
+        assert_eq!(
+            records,
+            [
+                "TN:",
+                "SF:sign.lox",
+                // the then arm of the `if` never ran, the else arm ran twice
+                "BRDA:2,0,0,0",
+                "BRDA:2,0,1,2",
+                "BRF:2",
+                "BRH:1",
+                "DA:1,1",
+                "DA:2,2",
+                "DA:3,0",
+                "DA:5,2",
+                "DA:7,1",
+                "DA:8,1",
+                "LF:6",
+                "LH:5",
+                "end_of_record",
+            ]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    process,
+    rc::Rc,
+};
+
+use crate::{
+    hook::Hook, interpreter::Interpreter, lox::Lox, parser::Parser, scanner::Scanner, stmt::Stmt,
+};
+
+const HELP: &str = "\
+Commands:
+  s, step            run to the next line, entering calls
+  n, next            run to the next line in this frame, stepping over calls
+  f, finish          run until the current call returns
+  c, continue        run until the next breakpoint
+  b, break [LINE]    set a breakpoint on LINE, or list breakpoints
+  d, delete LINE     remove the breakpoint on LINE
+  p, print EXPR      evaluate EXPR in the current scope and print it
+  locals             list local variables, innermost scope first
+  bt, backtrace      list the active call frames
+  l, list            show the source around the current line
+  q, quit            stop the program and exit
+An empty line repeats the previous command.";
+
+/// How far to run before prompting again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    Step,
+    Next { depth: usize },
+    Finish { depth: usize },
+    Continue,
+}
+
+/// Interactive source-level debugger, driven by commands read from stdin.
+///
+/// Execution stops before the first statement of the program, and afterwards whenever a
+/// statement starts a new line that matches a breakpoint or the current stepping mode.
+pub struct Debugger {
+    source: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    resume: Resume,
+    /// Frame depth and line of the last statement seen, so a line only stops once.
+    position: (usize, usize),
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.lines().map(String::from).collect(),
+            breakpoints: BTreeSet::new(),
+            resume: Resume::Step,
+            position: (0, 0),
+            last_command: String::new(),
+        }
+    }
+
+    fn should_stop(&self, depth: usize, line: usize) -> bool {
+        if self.breakpoints.contains(&line) {
+            return true;
+        }
+        match self.resume {
+            Resume::Step => true,
+            Resume::Next { depth: target } => depth <= target,
+            Resume::Finish { depth: target } => depth < target,
+            Resume::Continue => false,
+        }
+    }
+
+    fn prompt(&mut self, interpreter: &mut Interpreter, line: usize) {
+        println!("[line {}] {}", line, self.source_line(line));
+        loop {
+            print!("(rlox) ");
+            io::stdout().flush().unwrap();
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+                // nobody left to answer prompts, so let the program run to completion
+                self.breakpoints.clear();
+                self.resume = Resume::Continue;
+                return;
+            }
+
+            let mut input = input.trim().to_owned();
+            if input.is_empty() {
+                input = self.last_command.clone();
+            } else {
+                self.last_command = input.clone();
+            }
+            let (command, arg) = match input.split_once(' ') {
+                Some((command, arg)) => (command, arg.trim()),
+                None => (input.as_str(), ""),
+            };
+
+            let depth = interpreter.frames().len();
+            let resume = match command {
+                "s" | "step" => Some(Resume::Step),
+                "n" | "next" => Some(Resume::Next { depth }),
+                "f" | "finish" => Some(Resume::Finish { depth }),
+                "c" | "continue" => Some(Resume::Continue),
+                "b" | "break" => {
+                    self.set_breakpoint(arg);
+                    None
+                }
+                "d" | "delete" => {
+                    self.delete_breakpoint(arg);
+                    None
+                }
+                "p" | "print" => {
+                    self.print(interpreter, arg);
+                    None
+                }
+                "locals" => {
+                    self.locals(interpreter);
+                    None
+                }
+                "bt" | "backtrace" => {
+                    self.backtrace(interpreter);
+                    None
+                }
+                "l" | "list" => {
+                    self.list(line);
+                    None
+                }
+                "h" | "help" => {
+                    println!("{}", HELP);
+                    None
+                }
+                "q" | "quit" => process::exit(0),
+                "" => None,
+                _ => {
+                    println!(
+                        "Unknown command '{}'. Type 'help' for a list of commands.",
+                        command
+                    );
+                    None
+                }
+            };
+            if let Some(resume) = resume {
+                self.resume = resume;
+                return;
+            }
+        }
+    }
+
+    fn set_breakpoint(&mut self, arg: &str) {
+        if arg.is_empty() {
+            if self.breakpoints.is_empty() {
+                println!("No breakpoints.");
+            }
+            for line in &self.breakpoints {
+                println!("[line {}] {}", line, self.source_line(*line));
+            }
+            return;
+        }
+        match arg.parse::<usize>() {
+            Ok(line) if line >= 1 && line <= self.source.len() => {
+                self.breakpoints.insert(line);
+                println!("Breakpoint set on line {}.", line);
+            }
+            _ => println!("Expect a line number between 1 and {}.", self.source.len()),
+        }
+    }
+
+    fn delete_breakpoint(&mut self, arg: &str) {
+        match arg.parse::<usize>() {
+            Ok(line) if self.breakpoints.remove(&line) => {
+                println!("Breakpoint on line {} removed.", line)
+            }
+            _ => println!("No breakpoint on line '{}'.", arg),
+        }
+    }
+
+    fn print(&self, interpreter: &mut Interpreter, source: &str) {
+        let tokens = Scanner::new(source).scan_tokens();
+        let expr = Parser::new(&tokens).parse_expression();
+        if Lox::had_error() {
+            // the error has already been reported and must not fail the program itself
+            Lox::set_had_error(false);
+            return;
+        }
+        if let Some(expr) = expr {
+            match interpreter
+                .evaluate_unresolved(&expr)
+                .and_then(|value| interpreter.display(&value))
+            {
+                Ok(text) => println!("{}", text),
+                Err(e) => println!("Error: {}", e.msg),
+            }
+        }
+    }
+
+    fn locals(&self, interpreter: &Interpreter) {
+        let mut environment = interpreter.environment();
+        let mut depth = 0;
+        let mut found = false;
+        while !Rc::ptr_eq(&environment, &interpreter.globals) {
+            for (name, value) in environment.borrow().bindings() {
+                println!("[{}] {} = {}", depth, name, interpreter.stringify(&value));
+                found = true;
+            }
+            let enclosing = environment.borrow().enclosing.clone();
+            match enclosing {
+                Some(enclosing) => environment = enclosing,
+                None => break,
+            }
+            depth += 1;
+        }
+        if !found {
+            println!("No locals.");
+        }
+    }
+
+    fn backtrace(&self, interpreter: &Interpreter) {
+        for (i, frame) in interpreter.frames().iter().rev().enumerate() {
+            println!("#{} {} at line {}", i, frame.name, frame.line);
+        }
+    }
+
+    fn list(&self, line: usize) {
+        let first = line.saturating_sub(5).max(1);
+        let last = (line + 5).min(self.source.len());
+        for n in first..=last {
+            let marker = if n == line { "->" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&n) {
+                "*"
+            } else {
+                " "
+            };
+            println!("{}{}{:>4} {}", breakpoint, marker, n, self.source_line(n));
+        }
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        self.source
+            .get(line.wrapping_sub(1))
+            .map_or("", |s| s.as_str())
+    }
+}
+
+impl Hook for Debugger {
+    fn on_statement(&mut self, interpreter: &mut Interpreter, statement: &Stmt) {
+        let line = match statement.line() {
+            Some(line) => line,
+            None => return,
+        };
+        let depth = interpreter.frames().len();
+        if self.position == (depth, line) {
+            return;
+        }
+        self.position = (depth, line);
+        if self.should_stop(depth, line) {
+            self.prompt(interpreter, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_stop() {
+        let mut debugger = Debugger::new("var a = 1;\nvar b = 2;\nvar c = 3;\n");
+        assert!(debugger.should_stop(0, 1));
+
+        debugger.resume = Resume::Next { depth: 1 };
+        assert!(debugger.should_stop(1, 2));
+        assert!(debugger.should_stop(0, 2));
+        assert!(!debugger.should_stop(2, 2));
+
+        debugger.resume = Resume::Finish { depth: 1 };
+        assert!(!debugger.should_stop(1, 2));
+        assert!(debugger.should_stop(0, 2));
+
+        debugger.resume = Resume::Continue;
+        assert!(!debugger.should_stop(0, 2));
+        debugger.set_breakpoint("2");
+        assert!(debugger.should_stop(3, 2));
+        debugger.set_breakpoint("4");
+        assert!(!debugger.breakpoints.contains(&4));
+        debugger.delete_breakpoint("2");
+        assert!(!debugger.should_stop(3, 2));
+    }
+}
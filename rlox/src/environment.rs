@@ -1,10 +1,24 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
-
-use crate::{errors::RuntimeError, token::Token, value::Value};
-
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{errors::RuntimeError, gc::Trace, token::Token, value::Value};
+
+/// Variables of one scope.
+///
+/// Local variables live in `slots`, in the order they are defined, which is the index the
+/// `Resolver` assigned them. The global scope is the one without an enclosing scope and
+/// keeps its variables by name instead, since globals can be used before they are declared.
 pub struct Environment {
     pub enclosing: Option<Rc<RefCell<Environment>>>,
     values: HashMap<String, Value>,
+    slots: Vec<Value>,
+    /// Name of each slot, for lookups that were not resolved and for the debugger.
+    names: Vec<String>,
+    /// Globals that can't be assigned. Local constants are checked by the `Resolver`.
+    constants: HashSet<String>,
 }
 
 impl Environment {
@@ -12,6 +26,9 @@ impl Environment {
         Self {
             enclosing: None,
             values: HashMap::new(),
+            slots: Vec::new(),
+            names: Vec::new(),
+            constants: HashSet::new(),
         }
     }
 
@@ -19,15 +36,25 @@ impl Environment {
         Self {
             enclosing: Some(enclosing),
             values: HashMap::new(),
+            slots: Vec::new(),
+            names: Vec::new(),
+            constants: HashSet::new(),
         }
     }
 
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.names.iter().rposition(|n| n == name)
+    }
+
     pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            return Ok(self.values[&name.lexeme].clone());
+        if let Some(index) = self.slot(&name.lexeme) {
+            return Ok(self.slots[index].clone());
         }
-        if self.enclosing.is_some() {
-            return self.enclosing.as_ref().unwrap().borrow().get(name);
+        if let Some(value) = self.values.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+        if let Some(enclosing) = &self.enclosing {
+            return enclosing.borrow().get(name);
         }
         Err(RuntimeError::new(
             name.clone(),
@@ -36,7 +63,12 @@ impl Environment {
     }
 
     pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if let Some(index) = self.slot(&name.lexeme) {
+            self.slots[index] = value;
+            return Ok(());
+        }
         if self.values.contains_key(&name.lexeme) {
+            self.check_not_const(name)?;
             self.values.insert(name.lexeme.clone(), value);
             return Ok(());
         }
@@ -51,53 +83,125 @@ impl Environment {
         ))
     }
 
+    /// Value of `name` if it is a local defined directly in this scope.
+    pub fn get_local(&self, name: &str) -> Option<Value> {
+        self.slot(name).map(|index| self.slots[index].clone())
+    }
+
+    /// Defines a variable in this scope. Locals take the next free slot, so they must be
+    /// defined in the order the `Resolver` declared them.
     pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+        if self.enclosing.is_none() {
+            self.values.insert(name, value);
+        } else {
+            self.names.push(name);
+            self.slots.push(value);
+        }
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Option<Value> {
-        if distance == 0 {
-            return self.values.get(name).cloned();
+    /// Defines a variable declared in the script. Unlike `define`, this fails if `name` is
+    /// a global constant, since redeclaring it would be an assignment in disguise.
+    pub fn declare(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if self.enclosing.is_none() {
+            self.check_not_const(name)?;
+        }
+        self.define(name.lexeme.clone(), value);
+        Ok(())
+    }
+
+    /// Declares a variable that can't be assigned afterwards.
+    pub fn declare_const(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        self.declare(name, value)?;
+        self.mark_const(name.lexeme.clone());
+        Ok(())
+    }
+
+    /// Makes an already defined global a constant.
+    pub fn mark_const(&mut self, name: String) {
+        if self.enclosing.is_none() {
+            self.constants.insert(name);
         }
+    }
 
-        self.enclosing
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .get_at(distance - 1, name)
+    fn check_not_const(&self, name: &Token) -> Result<(), RuntimeError> {
+        if self.constants.contains(&name.lexeme) {
+            return Err(RuntimeError::new(
+                name.clone(),
+                format!("Can't assign to constant '{}'.", name.lexeme),
+            ));
+        }
+        Ok(())
+    }
 
-        // let mut environment = self.enclosing.clone().unwrap();
-        // for _ in 1..distance {
-        //     let enclosing = environment.borrow().enclosing.clone().unwrap();
-        //     environment = enclosing;
-        // }
+    /// Variables defined directly in this scope, locals in definition order and globals
+    /// sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut bindings: Vec<_> = self
+            .names
+            .iter()
+            .cloned()
+            .zip(self.slots.iter().cloned())
+            .collect();
+        bindings.extend(globals);
+        bindings
+    }
 
-        // let value = environment.borrow().values.get(name).cloned();
-        // value
+    /// Drops every variable and the link to the enclosing scope.
+    pub fn clear(&mut self) {
+        self.enclosing = None;
+        self.values.clear();
+        self.constants.clear();
+        self.slots.clear();
+        self.names.clear();
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: &Value) {
-        if distance == 0 {
-            self.values.insert(name.lexeme.clone(), value.clone());
-            return;
+    /// The scope `distance` levels out from `environment`.
+    fn ancestor(environment: &Rc<RefCell<Self>>, distance: usize) -> Rc<RefCell<Self>> {
+        let mut environment = environment.clone();
+        for _ in 0..distance {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("resolved scope depth is deeper than the scope chain");
+            environment = enclosing;
         }
+        environment
+    }
 
-        self.enclosing
-            .as_mut()
-            .unwrap()
-            .borrow_mut()
-            .assign_at(distance - 1, name, value);
+    pub fn get_at(&self, distance: usize, index: usize) -> Value {
+        if distance == 0 {
+            return self.slots[index].clone();
+        }
+        let enclosing = self.enclosing.as_ref().unwrap();
+        let value = Self::ancestor(enclosing, distance - 1).borrow().slots[index].clone();
+        value
+    }
 
-        // let mut environment = self.enclosing.clone().unwrap();
-        // for _ in 1..distance {
-        //     let enclosing = environment.borrow().enclosing.clone().unwrap();
-        //     environment = enclosing;
-        // }
+    pub fn assign_at(&mut self, distance: usize, index: usize, value: &Value) {
+        if distance == 0 {
+            self.slots[index] = value.clone();
+            return;
+        }
+        let enclosing = self.enclosing.as_ref().unwrap();
+        Self::ancestor(enclosing, distance - 1).borrow_mut().slots[index] = value.clone();
+    }
+}
 
-        // Rc::get_mut(&mut environment)
-        //     .unwrap()
-        //     .get_mut()
-        //     .values
-        //     .insert(name.lexeme.clone(), value.clone());
+impl Trace for Environment {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        if let Some(enclosing) = &self.enclosing {
+            refs.push(Rc::as_ptr(enclosing) as *const ());
+        }
+        for value in self.values.values().chain(&self.slots) {
+            value.trace(refs);
+        }
     }
 }
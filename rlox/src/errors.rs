@@ -1,10 +1,19 @@
-use crate::{token::Token, value::Value};
+use crate::{lox_function::LoxFunction, token::Token, value::Value};
 
 #[derive(Clone)]
 pub struct RuntimeError {
     pub token: Token,
     pub msg: String,
     pub return_value: Option<Value>,
+    /// Call a function returned with, to be made by its caller once the frame is gone.
+    pub tail_call: Option<Box<TailCall>>,
+}
+
+#[derive(Clone)]
+pub struct TailCall {
+    pub function: LoxFunction,
+    pub arguments: Vec<Value>,
+    pub keywords: Vec<(Token, Value)>,
 }
 
 impl RuntimeError {
@@ -13,6 +22,7 @@ impl RuntimeError {
             token,
             msg,
             return_value: None,
+            tail_call: None,
         }
     }
 
@@ -21,6 +31,25 @@ impl RuntimeError {
             token,
             msg,
             return_value,
+            tail_call: None,
+        }
+    }
+
+    pub fn new_tail_call(
+        token: Token,
+        function: LoxFunction,
+        arguments: Vec<Value>,
+        keywords: Vec<(Token, Value)>,
+    ) -> Self {
+        Self {
+            token,
+            msg: "return".into(),
+            return_value: None,
+            tail_call: Some(Box::new(TailCall {
+                function,
+                arguments,
+                keywords,
+            })),
         }
     }
 }
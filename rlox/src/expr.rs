@@ -1,11 +1,28 @@
+use std::rc::Rc;
+
 use ordered_float::OrderedFloat;
 
 use crate::token::{Token, TokenLiteral};
 
+/// Identifies a variable, assignment, `this`, `super` or call expression so the resolver
+/// can record which scope it refers to, or that it is a tail call. Assigned by the `Parser`
+/// and unique per process.
+pub type ExprId = usize;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Expr {
+    /// Assigns `value` to a variable. For a compound assignment such as `x += 1`,
+    /// `operator` is the arithmetic operator combining the current value with `value`.
     Assign {
+        id: ExprId,
         name: Token,
+        operator: Option<Token>,
+        value: Box<Expr>,
+    },
+    /// Assigns the elements of the tuple `value` evaluates to to the variables `targets`,
+    /// in order, as in `(a, b) = (b, a)`.
+    AssignTuple {
+        targets: Vec<(ExprId, Token)>,
         value: Box<Expr>,
     },
     Binary {
@@ -13,31 +30,49 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    /// Calls `callee` with positional `arguments` followed by `keywords`, the arguments
+    /// given by parameter name as in `f(1, b: 2)`.
     Call {
+        id: ExprId,
         callee: Box<Expr>,
         paren: Token,
         arguments: Vec<Expr>,
+        keywords: Vec<(Token, Expr)>,
+    },
+    Conditional {
+        condition: Box<Expr>,
+        question: Token,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
     },
     Get {
         object: Box<Expr>,
         name: Token,
     },
+    /// Assigns `value` to a property, combining it with the current value first if
+    /// `operator` is set, like `Assign`.
     Set {
         object: Box<Expr>,
         name: Token,
+        operator: Option<Token>,
         value: Box<Expr>,
     },
     Super {
+        id: ExprId,
         keyword: Token,
         method: Token,
     },
     This {
+        id: ExprId,
         keyword: Token,
     },
     Grouping {
         expression: Box<Expr>,
     },
+    /// A literal value. `token` is where it appears in the source, or `None` for literals
+    /// the parser or optimizer made up.
     Literal {
+        token: Option<Token>,
         value: TokenLiteral,
     },
     Logical {
@@ -45,22 +80,52 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    /// `(a, b)`, or the values of `return a, b;`. Always has at least two elements.
+    Tuple {
+        elements: Vec<Expr>,
+    },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
     Variable {
+        id: ExprId,
         name: Token,
     },
+    /// Suspends the generator running it, handing out `value`. Evaluates to the value the
+    /// generator is resumed with.
+    Yield {
+        keyword: Token,
+        value: Option<Box<Expr>>,
+    },
 }
 
 impl Expr {
-    pub fn new_assign(name: Token, value: Expr) -> Self {
+    pub fn new_assign(id: ExprId, name: Token, value: Expr) -> Self {
         Self::Assign {
+            id,
             name,
+            operator: None,
+            value: Box::new(value),
+        }
+    }
+
+    pub fn new_compound_assign(id: ExprId, name: Token, operator: Token, value: Expr) -> Self {
+        Self::Assign {
+            id,
+            name,
+            operator: Some(operator),
+            value: Box::new(value),
+        }
+    }
+
+    pub fn new_assign_tuple(targets: Vec<(ExprId, Token)>, value: Expr) -> Self {
+        Self::AssignTuple {
+            targets,
             value: Box::new(value),
         }
     }
+
     pub fn new_binary(left: Expr, operator: Token, right: Expr) -> Self {
         Self::Binary {
             left: Box::new(left),
@@ -69,11 +134,33 @@ impl Expr {
         }
     }
 
-    pub fn new_call(callee: Expr, paren: Token, arguments: Vec<Expr>) -> Self {
+    pub fn new_call(
+        id: ExprId,
+        callee: Expr,
+        paren: Token,
+        arguments: Vec<Expr>,
+        keywords: Vec<(Token, Expr)>,
+    ) -> Self {
         Self::Call {
+            id,
             callee: Box::new(callee),
             paren,
             arguments,
+            keywords,
+        }
+    }
+
+    pub fn new_conditional(
+        condition: Expr,
+        question: Token,
+        then_branch: Expr,
+        else_branch: Expr,
+    ) -> Self {
+        Self::Conditional {
+            condition: Box::new(condition),
+            question,
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
         }
     }
 
@@ -88,16 +175,30 @@ impl Expr {
         Self::Set {
             object: Box::new(object),
             name,
+            operator: None,
             value: Box::new(value),
         }
     }
 
-    pub fn new_super(keyword: Token, method: Token) -> Self {
-        Self::Super { keyword, method }
+    pub fn new_compound_set(object: Expr, name: Token, operator: Token, value: Expr) -> Self {
+        Self::Set {
+            object: Box::new(object),
+            name,
+            operator: Some(operator),
+            value: Box::new(value),
+        }
     }
 
-    pub fn new_this(keyword: Token) -> Self {
-        Self::This { keyword }
+    pub fn new_super(id: ExprId, keyword: Token, method: Token) -> Self {
+        Self::Super {
+            id,
+            keyword,
+            method,
+        }
+    }
+
+    pub fn new_this(id: ExprId, keyword: Token) -> Self {
+        Self::This { id, keyword }
     }
 
     pub fn new_grouping(expression: Expr) -> Self {
@@ -107,31 +208,34 @@ impl Expr {
     }
 
     pub fn new_literal(literal: TokenLiteral) -> Self {
-        Self::Literal { value: literal }
-    }
-
-    pub fn new_literal_nil() -> Self {
         Self::Literal {
-            value: TokenLiteral::Nil,
+            token: None,
+            value: literal,
         }
     }
 
-    pub fn new_literal_str(value: String) -> Self {
+    /// A literal written in the source as `token`.
+    pub fn new_literal_at(token: Token, literal: TokenLiteral) -> Self {
         Self::Literal {
-            value: TokenLiteral::String(value),
+            token: Some(token),
+            value: literal,
         }
     }
 
+    pub fn new_literal_nil() -> Self {
+        Self::new_literal(TokenLiteral::Nil)
+    }
+
+    pub fn new_literal_str(value: Rc<str>) -> Self {
+        Self::new_literal(TokenLiteral::String(value))
+    }
+
     pub fn new_literal_num(value: OrderedFloat<f64>) -> Self {
-        Self::Literal {
-            value: TokenLiteral::Number(value),
-        }
+        Self::new_literal(TokenLiteral::Number(value))
     }
 
     pub fn new_literal_bool(value: bool) -> Self {
-        Self::Literal {
-            value: TokenLiteral::Bool(value),
-        }
+        Self::new_literal(TokenLiteral::Bool(value))
     }
 
     pub fn new_logical(left: Expr, operator: Token, right: Expr) -> Self {
@@ -142,6 +246,10 @@ impl Expr {
         }
     }
 
+    pub fn new_tuple(elements: Vec<Expr>) -> Self {
+        Self::Tuple { elements }
+    }
+
     pub fn new_unary(operator: Token, right: Expr) -> Self {
         Self::Unary {
             operator,
@@ -149,56 +257,145 @@ impl Expr {
         }
     }
 
-    pub fn new_variable(name: Token) -> Self {
-        Self::Variable { name }
+    pub fn new_variable(id: ExprId, name: Token) -> Self {
+        Self::Variable { id, name }
+    }
+
+    pub fn new_yield(keyword: Token, value: Option<Expr>) -> Self {
+        Self::Yield {
+            keyword,
+            value: value.map(Box::new),
+        }
     }
 
     pub fn accept_visitor<V: ExprVisitor>(&self, visitor: &mut V) -> V::Output {
         match self {
-            Self::Assign { name, value } => visitor.visit_assign(name, value),
+            Self::Assign {
+                id,
+                name,
+                operator,
+                value,
+            } => visitor.visit_assign(*id, name, operator.as_ref(), value),
+            Self::AssignTuple { targets, value } => visitor.visit_assign_tuple(targets, value),
             Self::Binary {
                 left,
                 operator,
                 right,
             } => visitor.visit_binary(left, operator, right),
             Self::Call {
+                id,
                 callee,
                 paren,
                 arguments,
-            } => visitor.visit_call(callee, paren, arguments),
+                keywords,
+            } => visitor.visit_call(*id, callee, paren, arguments, keywords),
+            Self::Conditional {
+                condition,
+                question,
+                then_branch,
+                else_branch,
+            } => visitor.visit_conditional(condition, question, then_branch, else_branch),
             Self::Get { object, name } => visitor.visit_get(object, name),
             Self::Set {
                 object,
                 name,
+                operator,
                 value,
-            } => visitor.visit_set(object, name, value),
-            Self::Super { keyword, method } => visitor.visit_super(keyword, method),
-            Self::This { keyword } => visitor.visit_this(keyword),
+            } => visitor.visit_set(object, name, operator.as_ref(), value),
+            Self::Super {
+                id,
+                keyword,
+                method,
+            } => visitor.visit_super(*id, keyword, method),
+            Self::This { id, keyword } => visitor.visit_this(*id, keyword),
             Self::Grouping { expression } => visitor.visit_grouping(expression),
-            Self::Literal { value } => visitor.visit_literal(value),
+            Self::Literal { token, value } => visitor.visit_literal(token.as_ref(), value),
             Self::Logical {
                 left,
                 operator,
                 right,
             } => visitor.visit_logical(left, operator, right),
+            Self::Tuple { elements } => visitor.visit_tuple(elements),
             Self::Unary { operator, right } => visitor.visit_unary(operator, right),
-            Self::Variable { name } => visitor.visit_variable(name),
+            Self::Variable { id, name } => visitor.visit_variable(*id, name),
+            Self::Yield { keyword, value } => visitor.visit_yield(keyword, value.as_deref()),
+        }
+    }
+
+    /// Source line the expression starts on, if it contains a token.
+    pub fn line(&self) -> Option<usize> {
+        self.token().map(|token| token.line)
+    }
+
+    /// First token of the expression in the source, if it contains one.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Self::Assign { name, .. } | Self::Variable { name, .. } => Some(name),
+            Self::AssignTuple { targets, .. } => Some(&targets[0].1),
+            Self::Binary { left, operator, .. } | Self::Logical { left, operator, .. } => {
+                left.token().or(Some(operator))
+            }
+            Self::Call { callee, paren, .. } => callee.token().or(Some(paren)),
+            Self::Conditional {
+                condition,
+                question,
+                ..
+            } => condition.token().or(Some(question)),
+            Self::Get { object, name } | Self::Set { object, name, .. } => {
+                object.token().or(Some(name))
+            }
+            Self::Super { keyword, .. }
+            | Self::This { keyword, .. }
+            | Self::Yield { keyword, .. } => Some(keyword),
+            Self::Grouping { expression } => expression.token(),
+            Self::Literal { token, .. } => token.as_ref(),
+            Self::Tuple { elements } => elements.iter().find_map(Expr::token),
+            Self::Unary { operator, .. } => Some(operator),
         }
     }
 }
 pub trait ExprVisitor {
     type Output;
 
-    fn visit_assign(&mut self, name: &Token, value: &Expr) -> Self::Output;
+    fn visit_assign(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output;
+    fn visit_assign_tuple(&mut self, targets: &[(ExprId, Token)], value: &Expr) -> Self::Output;
     fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output;
-    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Self::Output;
+    fn visit_call(
+        &mut self,
+        id: ExprId,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output;
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output;
     fn visit_get(&mut self, object: &Expr, name: &Token) -> Self::Output;
-    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) -> Self::Output;
-    fn visit_super(&mut self, keyword: &Token, method: &Token) -> Self::Output;
-    fn visit_this(&mut self, keyword: &Token) -> Self::Output;
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output;
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, method: &Token) -> Self::Output;
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> Self::Output;
     fn visit_grouping(&mut self, expression: &Expr) -> Self::Output;
-    fn visit_literal(&mut self, value: &TokenLiteral) -> Self::Output;
+    fn visit_literal(&mut self, token: Option<&Token>, value: &TokenLiteral) -> Self::Output;
     fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output;
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output;
     fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Self::Output;
-    fn visit_variable(&mut self, name: &Token) -> Self::Output;
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> Self::Output;
+    fn visit_yield(&mut self, keyword: &Token, value: Option<&Expr>) -> Self::Output;
 }
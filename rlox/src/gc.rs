@@ -0,0 +1,372 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    environment::Environment, lox_generator::LoxGenerator, lox_instance::LoxInstance, value::Value,
+};
+
+/// Number of allocations before the first automatic collection.
+const INITIAL_THRESHOLD: usize = 10_000;
+
+/// Implemented by everything that can hold a reference to a tracked object.
+pub trait Trace {
+    /// Pushes the address of every tracked object `self` directly refers to.
+    fn trace(&self, refs: &mut Vec<*const ()>);
+}
+
+/// Counts reported by `stats` and `collect`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    /// Environments that are still alive.
+    pub environments: usize,
+    /// Instances that are still alive.
+    pub instances: usize,
+    /// Generators that are still alive.
+    pub generators: usize,
+    /// Tuples that are still alive.
+    pub tuples: usize,
+    /// Collections run so far, whether automatic or through `gc()`.
+    pub collections: usize,
+    /// Objects reclaimed by collections so far.
+    pub reclaimed: usize,
+}
+
+/// Every environment, instance, generator and tuple allocated on this thread.
+///
+/// Reference counting frees most objects as soon as they become unreachable, so the heap
+/// only needs to find garbage that keeps itself alive through a cycle, such as a closure
+/// stored in its own environment or an instance holding one of its bound methods.
+///
+/// Tuples can't be changed, so a cycle through one always runs through a mutable object
+/// too. They are tracked so that references through them are counted, but clearing the
+/// other objects of a cycle is what frees them.
+struct Heap {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    instances: Vec<Weak<RefCell<LoxInstance>>>,
+    generators: Vec<Weak<RefCell<LoxGenerator>>>,
+    tuples: Vec<Weak<[Value]>>,
+    allocated: usize,
+    threshold: usize,
+    collections: usize,
+    reclaimed: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const { RefCell::new(Heap {
+        environments: Vec::new(),
+        instances: Vec::new(),
+        generators: Vec::new(),
+        tuples: Vec::new(),
+        allocated: 0,
+        threshold: INITIAL_THRESHOLD,
+        collections: 0,
+        reclaimed: 0,
+    }) };
+}
+
+/// A tracked object, kept alive for the duration of a collection.
+enum Object {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Generator(Rc<RefCell<LoxGenerator>>),
+    Tuple(Rc<[Value]>),
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match self {
+            Self::Environment(environment) => Rc::as_ptr(environment) as *const (),
+            Self::Instance(instance) => Rc::as_ptr(instance) as *const (),
+            Self::Generator(generator) => Rc::as_ptr(generator) as *const (),
+            Self::Tuple(elements) => Rc::as_ptr(elements) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Self::Environment(environment) => Rc::strong_count(environment),
+            Self::Instance(instance) => Rc::strong_count(instance),
+            Self::Generator(generator) => Rc::strong_count(generator),
+            Self::Tuple(elements) => Rc::strong_count(elements),
+        }
+    }
+
+    /// Addresses this object refers to, or `None` if it is borrowed and can't be inspected.
+    fn references(&self) -> Option<Vec<*const ()>> {
+        let mut refs = Vec::new();
+        match self {
+            Self::Environment(environment) => environment.try_borrow().ok()?.trace(&mut refs),
+            Self::Instance(instance) => instance.try_borrow().ok()?.trace(&mut refs),
+            Self::Generator(generator) => generator.try_borrow().ok()?.trace(&mut refs),
+            Self::Tuple(elements) => {
+                for element in elements.iter() {
+                    element.trace(&mut refs);
+                }
+            }
+        }
+        Some(refs)
+    }
+
+    /// Drops everything the object refers to, breaking any cycle through it.
+    fn clear(&self) {
+        match self {
+            Self::Environment(environment) => environment.borrow_mut().clear(),
+            Self::Instance(instance) => instance.borrow_mut().clear(),
+            Self::Generator(generator) => generator.borrow_mut().clear(),
+            Self::Tuple(_) => {}
+        }
+    }
+}
+
+pub fn alloc_environment(environment: Environment) -> Rc<RefCell<Environment>> {
+    let environment = Rc::new(RefCell::new(environment));
+    let collect_now = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.push(Rc::downgrade(&environment));
+        heap.allocated()
+    });
+    if collect_now {
+        collect();
+    }
+    environment
+}
+
+pub fn alloc_instance(instance: LoxInstance) -> Rc<RefCell<LoxInstance>> {
+    let instance = Rc::new(RefCell::new(instance));
+    let collect_now = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.instances.push(Rc::downgrade(&instance));
+        heap.allocated()
+    });
+    if collect_now {
+        collect();
+    }
+    instance
+}
+
+pub fn alloc_generator(generator: LoxGenerator) -> Rc<RefCell<LoxGenerator>> {
+    let generator = Rc::new(RefCell::new(generator));
+    let collect_now = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.generators.push(Rc::downgrade(&generator));
+        heap.allocated()
+    });
+    if collect_now {
+        collect();
+    }
+    generator
+}
+
+pub fn alloc_tuple(elements: Vec<Value>) -> Rc<[Value]> {
+    let elements: Rc<[Value]> = elements.into();
+    let collect_now = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tuples.push(Rc::downgrade(&elements));
+        heap.allocated()
+    });
+    if collect_now {
+        collect();
+    }
+    elements
+}
+
+impl Heap {
+    /// Records an allocation and returns whether it is time to collect.
+    fn allocated(&mut self) -> bool {
+        self.allocated += 1;
+        self.allocated >= self.threshold
+    }
+
+    /// Forgets objects that have already been freed.
+    fn retain_live(&mut self) {
+        self.environments.retain(|weak| weak.strong_count() > 0);
+        self.instances.retain(|weak| weak.strong_count() > 0);
+        self.generators.retain(|weak| weak.strong_count() > 0);
+        self.tuples.retain(|weak| weak.strong_count() > 0);
+    }
+
+    fn live(&self) -> GcStats {
+        GcStats {
+            environments: self
+                .environments
+                .iter()
+                .filter(|weak| weak.strong_count() > 0)
+                .count(),
+            instances: self
+                .instances
+                .iter()
+                .filter(|weak| weak.strong_count() > 0)
+                .count(),
+            generators: self
+                .generators
+                .iter()
+                .filter(|weak| weak.strong_count() > 0)
+                .count(),
+            tuples: self
+                .tuples
+                .iter()
+                .filter(|weak| weak.strong_count() > 0)
+                .count(),
+            collections: self.collections,
+            reclaimed: self.reclaimed,
+        }
+    }
+}
+
+/// Live object counts for this thread.
+pub fn stats() -> GcStats {
+    HEAP.with(|heap| heap.borrow().live())
+}
+
+/// Reclaims objects that are only reachable through cycles among themselves, and returns
+/// the updated stats.
+///
+/// Works by trial deletion: references between tracked objects are subtracted from their
+/// reference counts, and any object with references left over is held from outside the
+/// heap, by the interpreter or the host. Everything reachable from those roots is kept and
+/// the rest is garbage. Objects that are borrowed while the collector runs count as roots.
+pub fn collect() -> GcStats {
+    let objects: Vec<Object> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.retain_live();
+        let environments = heap.environments.iter().filter_map(Weak::upgrade);
+        let instances = heap.instances.iter().filter_map(Weak::upgrade);
+        let generators = heap.generators.iter().filter_map(Weak::upgrade);
+        let tuples = heap.tuples.iter().filter_map(Weak::upgrade);
+        environments
+            .map(Object::Environment)
+            .chain(instances.map(Object::Instance))
+            .chain(generators.map(Object::Generator))
+            .chain(tuples.map(Object::Tuple))
+            .collect()
+    });
+
+    let indices: HashMap<*const (), usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (object.address(), i))
+        .collect();
+    let references: Vec<Option<Vec<*const ()>>> = objects.iter().map(Object::references).collect();
+
+    let mut internal = vec![0; objects.len()];
+    for refs in references.iter().flatten() {
+        for address in refs {
+            if let Some(&i) = indices.get(address) {
+                internal[i] += 1;
+            }
+        }
+    }
+
+    // one reference to each object is the one held by `objects`
+    let mut reachable: Vec<bool> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| references[i].is_none() || object.strong_count() - 1 > internal[i])
+        .collect();
+    let mut pending: Vec<usize> = (0..objects.len()).filter(|i| reachable[*i]).collect();
+    while let Some(i) = pending.pop() {
+        for address in references[i].iter().flatten() {
+            if let Some(&j) = indices.get(address) {
+                if !reachable[j] {
+                    reachable[j] = true;
+                    pending.push(j);
+                }
+            }
+        }
+    }
+
+    let mut reclaimed = 0;
+    for (object, reachable) in objects.iter().zip(&reachable) {
+        if !reachable {
+            object.clear();
+            reclaimed += 1;
+        }
+    }
+    drop(objects);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.retain_live();
+        heap.collections += 1;
+        heap.reclaimed += reclaimed;
+        heap.allocated = 0;
+        let live = heap.environments.len()
+            + heap.instances.len()
+            + heap.generators.len()
+            + heap.tuples.len();
+        heap.threshold = INITIAL_THRESHOLD.max(live * 2);
+        heap.live()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpreter::Interpreter,
+        lox::{Lox, Options},
+    };
+
+    fn run(source: &str) -> Rc<RefCell<Interpreter>> {
+        Lox::run(source, "<test>", &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn test_collect_closure_cycles() {
+        let _interpreter = run("
+            fun make() {
+                fun f() { return f; }
+                return f;
+            }
+            for (var i = 0; i < 100; i = i + 1) make();
+        ");
+        let before = stats();
+        let after = collect();
+        // every call to make leaves its scope alive through the closure stored in it
+        assert!(before.environments >= 100);
+        assert!(after.environments < 10);
+        assert!(after.reclaimed >= 100);
+    }
+
+    #[test]
+    fn test_collect_instance_cycles() {
+        let _interpreter = run("
+            class Node {
+                init() { this.me = this.get; }
+                get() { return this; }
+            }
+            for (var i = 0; i < 100; i = i + 1) Node();
+        ");
+        assert!(stats().instances >= 100);
+        let after = collect();
+        assert_eq!(after.instances, 0);
+    }
+
+    #[test]
+    fn test_collect_keeps_reachable_cycles() {
+        let interpreter = run("
+            class Node {
+                init() { this.me = this.get; }
+                get() { return this; }
+            }
+            var kept = Node();
+            kept.value = \"alive\";
+            for (var i = 0; i < 100; i = i + 1) Node();
+            var reclaimed = gc();
+            var result = kept.me().me().value;
+        ");
+        let interpreter = interpreter.borrow();
+        let globals = interpreter.globals.borrow();
+        let bindings = globals.bindings();
+        let global = |name: &str| {
+            let (_, value) = bindings.iter().find(|(n, _)| n == name).unwrap();
+            interpreter.stringify(value)
+        };
+        assert_eq!(global("result"), "alive");
+        assert_eq!(global("reclaimed"), "200");
+        assert_eq!(stats().instances, 1);
+    }
+}
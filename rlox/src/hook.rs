@@ -0,0 +1,27 @@
+use crate::{interpreter::Interpreter, stmt::Stmt, token::Token, value::Value};
+
+/// Observer notified by the `Interpreter` as it runs a program.
+///
+/// Hooks are temporarily detached while one of their methods runs, so any code a hook
+/// evaluates through the interpreter is not itself observed.
+pub trait Hook {
+    /// Called before `statement` is executed.
+    fn on_statement(&mut self, _interpreter: &mut Interpreter, _statement: &Stmt) {}
+
+    /// Called when execution takes one arm of a branch, identified by the `if`, `while`, `for`,
+    /// `and` or `or` token that introduces it.
+    ///
+    /// Arm 0 is the `then` branch, the loop body, or the short-circuit of a logical operator.
+    /// Arm 1 is the `else` branch (taken or implied), the loop exit, or evaluating the right
+    /// operand of a logical operator.
+    fn on_branch(&mut self, _interpreter: &mut Interpreter, _token: &Token, _arm: usize) {}
+
+    /// Called after a frame for `callee` has been pushed, before its body runs.
+    fn on_call(&mut self, _interpreter: &mut Interpreter, _callee: &Value) {}
+
+    /// Called when `callee` returns, before its frame is popped.
+    fn on_return(&mut self, _interpreter: &mut Interpreter, _callee: &Value) {}
+
+    /// Called once the program has finished running, whether or not it succeeded.
+    fn on_finish(&mut self, _interpreter: &mut Interpreter) {}
+}
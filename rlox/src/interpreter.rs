@@ -1,74 +1,522 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    rc::Rc,
+    slice,
+};
 
+use num_bigint_dig::BigInt;
+use num_traits::{Pow, Signed, ToPrimitive, Zero};
 use ordered_float::OrderedFloat;
 
 use crate::{
     environment::Environment,
     errors::RuntimeError,
-    expr::{Expr, ExprVisitor},
+    expr::{Expr, ExprId, ExprVisitor},
+    gc::{self, Trace},
+    hook::Hook,
     lox::Lox,
-    lox_callable::LoxCallable,
+    lox_callable::{KeywordArguments, LoxCallable},
     lox_class::LoxClass,
     lox_function::LoxFunction,
+    lox_generator::LoxGenerator,
     lox_instance::LoxInstance,
-    native_functions::CLOCK_FN,
-    stmt::{Stmt, StmtVisitor},
+    lox_trait::LoxTrait,
+    native_functions::{
+        BIGINT_FN, CLASS_OF_FN, CLOCK_FN, FIELDS_FN, GC_FN, GC_STATS_FN, GET_FIELD_FN,
+        HAS_FIELD_FN, IS_INSTANCE_FN, METHODS_FN, NAME_OF_FN, RUN_FN, SET_FIELD_FN, SPAWN_FN,
+        SUPERCLASS_OF_FN,
+    },
+    pattern::Pattern,
+    stmt::{FunctionDecl, MatchArm, Stmt, StmtVisitor, TypeAnnotation},
     token::{Token, TokenLiteral, TokenType},
     value::Value,
 };
 
+/// Name of the method an instance on the left of a binary operator can define to
+/// overload it.
+fn operator_method(operator: TokenType) -> Option<&'static str> {
+    Some(match operator {
+        TokenType::Ampersand => "__and__",
+        TokenType::BangEqual | TokenType::EqualEqual => "__eq__",
+        TokenType::Caret => "__xor__",
+        TokenType::Greater => "__gt__",
+        TokenType::GreaterEqual => "__ge__",
+        TokenType::GreaterGreater => "__rshift__",
+        TokenType::Less => "__lt__",
+        TokenType::LessEqual => "__le__",
+        TokenType::LessLess => "__lshift__",
+        TokenType::Minus => "__sub__",
+        TokenType::Percent => "__mod__",
+        TokenType::Pipe => "__or__",
+        TokenType::Plus => "__add__",
+        TokenType::Slash => "__div__",
+        TokenType::Star => "__mul__",
+        TokenType::StarStar => "__pow__",
+        TokenType::TildeSlash => "__floordiv__",
+        _ => return None,
+    })
+}
+
+/// Name of the method an instance on the right of a binary operator can define to
+/// overload it, which is called with the left operand. Comparisons call the mirrored
+/// comparison, so `1 < x` calls `x.__gt__(1)`, and `==` and `!=` call `__eq__` either way.
+fn reflected_operator_method(operator: TokenType) -> Option<&'static str> {
+    Some(match operator {
+        TokenType::Ampersand => "__rand__",
+        TokenType::BangEqual | TokenType::EqualEqual => "__eq__",
+        TokenType::Caret => "__rxor__",
+        TokenType::Greater => "__lt__",
+        TokenType::GreaterEqual => "__le__",
+        TokenType::GreaterGreater => "__rrshift__",
+        TokenType::Less => "__gt__",
+        TokenType::LessEqual => "__ge__",
+        TokenType::LessLess => "__rlshift__",
+        TokenType::Minus => "__rsub__",
+        TokenType::Percent => "__rmod__",
+        TokenType::Pipe => "__ror__",
+        TokenType::Plus => "__radd__",
+        TokenType::Slash => "__rdiv__",
+        TokenType::Star => "__rmul__",
+        TokenType::StarStar => "__rpow__",
+        TokenType::TildeSlash => "__rfloordiv__",
+        _ => return None,
+    })
+}
+
+fn operator_not_defined(
+    operator: &Token,
+    instance: &Rc<RefCell<LoxInstance>>,
+    name: &str,
+) -> RuntimeError {
+    RuntimeError::new(
+        operator.clone(),
+        format!(
+            "Operator '{}' is not defined for {} instances. Define '{}' to overload it.",
+            operator.lexeme,
+            instance.borrow().class(),
+            name
+        ),
+    )
+}
+
+/// Operands of a numeric binary operator. An integer mixed with a big integer is promoted
+/// to a big integer, and anything mixed with a float is promoted to a float.
+enum Operands {
+    Integers(i64, i64),
+    BigInts(BigInt, BigInt),
+    Floats(OrderedFloat<f64>, OrderedFloat<f64>),
+}
+
+/// Where a `for`-`in` loop is in the value it iterates over.
+pub enum Iteration {
+    /// The characters of a string from a byte offset on.
+    Chars(Rc<str>, usize),
+    /// The elements of a tuple from an index on.
+    Elements(Rc<[Value]>, usize),
+    /// The bound `next` method of an iterator instance.
+    Next(Value),
+    /// A generator, which is exhausted once its body finishes.
+    Generator(Rc<RefCell<LoxGenerator>>),
+}
+
+impl Trace for Iteration {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        match self {
+            Self::Chars(..) => {}
+            Self::Elements(elements, _) => refs.push(Rc::as_ptr(elements) as *const ()),
+            Self::Next(next) => next.trace(refs),
+            Self::Generator(generator) => refs.push(Rc::as_ptr(generator) as *const ()),
+        }
+    }
+}
+
+/// Moves an error raised by a native function to `token`. Natives have no token of their
+/// own to report an error at, while errors from Lox code they run already have one.
+fn at_token(error: RuntimeError, token: &Token) -> RuntimeError {
+    if error.token.ttype == TokenType::EOF {
+        RuntimeError::new(token.clone(), error.msg)
+    } else {
+        error
+    }
+}
+
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
     environment: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    /// Scope depth and slot index of every resolved local variable.
+    locals: HashMap<ExprId, (usize, usize)>,
+    /// Calls whose value is returned directly, so they can reuse the caller's native frame.
+    tail_calls: HashSet<ExprId>,
+    hooks: Vec<Box<dyn Hook>>,
+    frames: Vec<CallFrame>,
+    /// Value a suspended generator is resumed with, for the `yield` it stopped at.
+    resumed: Option<Value>,
+    /// Coroutines waiting for their turn to run, see `spawn` and `run`.
+    tasks: VecDeque<Rc<RefCell<LoxGenerator>>>,
+    /// Whether function and class names are constants.
+    strict: bool,
+}
+
+/// A Lox call in progress, tracked while hooks are attached.
+pub struct CallFrame {
+    /// Name of the function or class that was called.
+    pub name: String,
+    /// Line currently executing in this frame.
+    pub line: usize,
+}
+
+impl CallFrame {
+    pub fn new(name: String, line: usize) -> Self {
+        Self { name, line }
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         let mut environment = Environment::new();
         environment.define("clock".into(), Value::NativeFn(&CLOCK_FN));
-        let globals = Rc::new(RefCell::new(environment));
+        environment.define("gc".into(), Value::NativeFn(&GC_FN));
+        environment.define("gc_stats".into(), Value::NativeFn(&GC_STATS_FN));
+        environment.define("bigint".into(), Value::NativeFn(&BIGINT_FN));
+        environment.define("spawn".into(), Value::NativeFn(&SPAWN_FN));
+        environment.define("run".into(), Value::NativeFn(&RUN_FN));
+        environment.define("class_of".into(), Value::NativeFn(&CLASS_OF_FN));
+        environment.define("name_of".into(), Value::NativeFn(&NAME_OF_FN));
+        environment.define("superclass_of".into(), Value::NativeFn(&SUPERCLASS_OF_FN));
+        environment.define("fields".into(), Value::NativeFn(&FIELDS_FN));
+        environment.define("methods".into(), Value::NativeFn(&METHODS_FN));
+        environment.define("has_field".into(), Value::NativeFn(&HAS_FIELD_FN));
+        environment.define("get_field".into(), Value::NativeFn(&GET_FIELD_FN));
+        environment.define("set_field".into(), Value::NativeFn(&SET_FIELD_FN));
+        environment.define("is_instance".into(), Value::NativeFn(&IS_INSTANCE_FN));
+        let globals = gc::alloc_environment(environment);
         Self {
             environment: globals.clone(),
             globals,
             locals: HashMap::new(),
+            tail_calls: HashSet::new(),
+            hooks: Vec::new(),
+            frames: Vec::new(),
+            resumed: None,
+            tasks: VecDeque::new(),
+            strict: false,
         }
     }
 
+    /// Makes function and class names constants, so assigning to them is an error.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
     pub fn interpret(&mut self, statements: &Vec<Stmt>) {
+        if !self.hooks.is_empty() {
+            self.frames.push(CallFrame::new("<script>".into(), 0));
+        }
         for stmt in statements {
             let res = self.execute(stmt);
             match res {
                 Err(e) => {
                     Lox::runtime_error(e);
-                    return;
+                    break;
                 }
                 _ => {}
             };
         }
+        self.notify(|hook, interpreter| hook.on_finish(interpreter));
+        self.frames.clear();
     }
 
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.clone(), depth);
+    /// Attaches a hook that is notified of statements and calls as the program runs.
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
     }
 
-    fn look_up_variable(&self, name: &Token, expr: &Expr) -> Result<Value, RuntimeError> {
-        let distance = self.locals.get(expr);
-        if let Some(distance) = distance {
-            Ok(self
-                .environment
-                .borrow()
-                .get_at(*distance, &name.lexeme)
-                .unwrap())
+    /// Active call frames, innermost last. Only tracked while hooks are attached.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    /// The innermost scope of the code currently executing.
+    pub fn environment(&self) -> Rc<RefCell<Environment>> {
+        self.environment.clone()
+    }
+
+    /// Makes `environment` the innermost scope and returns the one it replaces.
+    pub fn set_environment(
+        &mut self,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Rc<RefCell<Environment>> {
+        std::mem::replace(&mut self.environment, environment)
+    }
+
+    /// Evaluates an expression that was never seen by the resolver, such as one typed at a
+    /// debugger prompt. Variables are looked up by name through the current scope chain.
+    pub fn evaluate_unresolved(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        // unresolved variables fall back to `globals`, so point it at the current scope
+        let globals = std::mem::replace(&mut self.globals, self.environment.clone());
+        let res = self.evaluate(expr);
+        self.globals = globals;
+        res
+    }
+
+    fn notify(&mut self, mut f: impl FnMut(&mut dyn Hook, &mut Self)) {
+        let mut hooks = std::mem::take(&mut self.hooks);
+        for hook in hooks.iter_mut() {
+            f(hook.as_mut(), self);
+        }
+        self.hooks = hooks;
+    }
+
+    pub fn resolve(&mut self, id: ExprId, depth: usize, index: usize) {
+        self.locals.insert(id, (depth, index));
+    }
+
+    pub fn mark_tail_call(&mut self, id: ExprId) {
+        self.tail_calls.insert(id);
+    }
+
+    fn look_up_variable(&self, id: ExprId, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some((distance, index)) = self.locals.get(&id) {
+            Ok(self.environment.borrow().get_at(*distance, *index))
         } else {
             self.globals.borrow().get(name)
         }
     }
 
-    fn execute(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
+    fn assign_variable(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        value: &Value,
+    ) -> Result<(), RuntimeError> {
+        if let Some((distance, index)) = self.locals.get(&id) {
+            self.environment
+                .borrow_mut()
+                .assign_at(*distance, *index, value);
+            Ok(())
+        } else {
+            self.globals.borrow_mut().assign(name, value.clone())
+        }
+    }
+
+    /// The elements of the tuple `value`, one for each of `names`. A mismatch is reported
+    /// at the first name left without a value, or at the last name if there are values
+    /// left over.
+    fn unpack(names: &[&Token], value: &Value) -> Result<Rc<[Value]>, RuntimeError> {
+        let Value::Tuple(elements) = value else {
+            return Err(RuntimeError::new(
+                names[0].clone(),
+                "Can only destructure tuples.".into(),
+            ));
+        };
+        if elements.len() != names.len() {
+            let name = names.get(elements.len()).unwrap_or(&names[names.len() - 1]);
+            return Err(RuntimeError::new(
+                (*name).clone(),
+                format!(
+                    "Expected {} values to destructure but got {}.",
+                    names.len(),
+                    elements.len()
+                ),
+            ));
+        }
+        Ok(elements.clone())
+    }
+
+    pub fn execute(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
+        self.enter_statement(statement);
         statement.accept_visitor(self)
     }
 
+    /// Tells hooks that `statement` is about to run.
+    pub fn enter_statement(&mut self, statement: &Stmt) {
+        if !self.hooks.is_empty() {
+            if let (Some(frame), Some(line)) = (self.frames.last_mut(), statement.line()) {
+                frame.line = line;
+            }
+            self.notify(|hook, interpreter| hook.on_statement(interpreter, statement));
+        }
+    }
+
+    /// Runs the rest of a statement a generator was suspended in, after `resume_yield`.
+    /// Hooks were told about the statement when it started.
+    pub fn execute_resumed(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
+        statement.accept_visitor(self)
+    }
+
+    /// Makes the next `yield` evaluated produce `value`, as the generator it suspended
+    /// continues.
+    pub fn resume_yield(&mut self, value: Value) {
+        self.resumed = Some(value);
+    }
+
+    /// Queues a coroutine for `run_tasks`.
+    pub fn spawn(&mut self, task: Rc<RefCell<LoxGenerator>>) {
+        self.tasks.push_back(task);
+    }
+
+    /// Runs the queued coroutines in turn, each up to its next `yield`, until every one of
+    /// them has finished. Coroutines may spawn more as they run.
+    pub fn run_tasks(&mut self) -> Result<(), RuntimeError> {
+        while let Some(task) = self.tasks.pop_front() {
+            LoxGenerator::resume(&task, self, Value::Nil)?;
+            if !task.borrow().is_done() {
+                self.tasks.push_back(task);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells hooks which arm of the branch at `token` is about to run.
+    pub fn branch(&mut self, token: &Token, arm: usize) {
+        if !self.hooks.is_empty() {
+            self.notify(|hook, interpreter| hook.on_branch(interpreter, token, arm));
+        }
+    }
+
+    fn call_with_frame(
+        &mut self,
+        callee: &Value,
+        function: &dyn LoxCallable,
+        paren: &Token,
+        arguments: &[Value],
+        keywords: &[(Token, Value)],
+    ) -> Result<Value, RuntimeError> {
+        self.frames
+            .push(CallFrame::new(function.name(), paren.line));
+        self.notify(|hook, interpreter| hook.on_call(interpreter, callee));
+        let res = function.call_with_keywords(self, arguments, keywords);
+        self.notify(|hook, interpreter| hook.on_return(interpreter, callee));
+        self.frames.pop();
+        res
+    }
+
+    /// Evaluates the callee and arguments of a call and checks that they fit together.
+    fn evaluate_call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Result<(Value, Vec<Value>, KeywordArguments), RuntimeError> {
+        let callee = self.evaluate(callee)?;
+
+        let mut argument_values = Vec::new();
+        for argument in arguments {
+            argument_values.push(self.evaluate(argument)?);
+        }
+        let mut keyword_values = Vec::new();
+        for (name, argument) in keywords {
+            keyword_values.push((name.clone(), self.evaluate(argument)?));
+        }
+
+        let function: &dyn LoxCallable = match &callee {
+            Value::NativeFn(f) => *f,
+            Value::NativeMethod(f) => f.as_ref(),
+            Value::LoxFn(f) => f,
+            Value::LoxClass(f) => f,
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Can only call functions and classes.".into(),
+                ));
+            }
+        };
+
+        Self::check_arguments(function, paren, arguments.len(), keywords)?;
+        Ok((callee, argument_values, keyword_values))
+    }
+
+    /// Checks that a call with `count` positional arguments and `keywords` gives every
+    /// parameter of `function` without a default exactly one value.
+    fn check_arguments(
+        function: &dyn LoxCallable,
+        paren: &Token,
+        count: usize,
+        keywords: &[(Token, Expr)],
+    ) -> Result<(), RuntimeError> {
+        let arity = function.arity();
+        let parameters = function.parameters();
+        let too_many = !arity.variadic && count > arity.required + arity.optional;
+        if too_many || (keywords.is_empty() && !arity.accepts(count)) {
+            let expected = match function.signature() {
+                Some(signature) => format!("{} to {}", arity, signature),
+                None => arity.to_string(),
+            };
+            return Err(RuntimeError::new(
+                paren.clone(),
+                format!("Expected {} but got {}.", expected, count),
+            ));
+        }
+        for (name, _) in keywords {
+            match parameters.iter().position(|param| *param == name.lexeme) {
+                Some(index) if index < count => {
+                    return Err(RuntimeError::new(
+                        name.clone(),
+                        format!("Argument '{}' was already given by position.", name.lexeme),
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    return Err(RuntimeError::new(
+                        name.clone(),
+                        format!(
+                            "'{}' has no parameter called '{}'.",
+                            function.name(),
+                            name.lexeme
+                        ),
+                    ));
+                }
+            }
+        }
+        for param in parameters.iter().take(arity.required).skip(count) {
+            if !keywords.iter().any(|(name, _)| name.lexeme == *param) {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    format!("Missing argument for parameter '{}'.", param),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls a value already checked by `evaluate_call`.
+    fn call(
+        &mut self,
+        callee: &Value,
+        paren: &Token,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        self.call_with_keywords(callee, paren, arguments, &[])
+    }
+
+    fn call_with_keywords(
+        &mut self,
+        callee: &Value,
+        paren: &Token,
+        arguments: &[Value],
+        keywords: &[(Token, Value)],
+    ) -> Result<Value, RuntimeError> {
+        let function: &dyn LoxCallable = match callee {
+            Value::NativeFn(f) => *f,
+            Value::NativeMethod(f) => f.as_ref(),
+            Value::LoxFn(f) => f,
+            Value::LoxClass(f) => f,
+            _ => unreachable!("callee was checked by evaluate_call"),
+        };
+        let res = if self.hooks.is_empty() {
+            function.call_with_keywords(self, arguments, keywords)
+        } else {
+            self.call_with_frame(callee, function, paren, arguments, keywords)
+        };
+        match callee {
+            Value::NativeFn(_) | Value::NativeMethod(_) => res.map_err(|e| at_token(e, paren)),
+            _ => res,
+        }
+    }
+
     pub fn execute_block(
         &mut self,
         statements: &Vec<Option<Stmt>>,
@@ -87,11 +535,24 @@ impl Interpreter {
         res
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> <Self as ExprVisitor>::Output {
+    /// Executes a single statement in `environment`, like the body of a `for`-`in` loop.
+    fn execute_in(
+        &mut self,
+        statement: &Stmt,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<(), RuntimeError> {
+        let previous = self.environment.clone();
+        self.environment = environment;
+        let res = self.execute(statement);
+        self.environment = previous;
+        res
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> <Self as ExprVisitor>::Output {
         expr.accept_visitor(self)
     }
 
-    fn is_truthy(&self, value: &Value) -> bool {
+    pub fn is_truthy(&self, value: &Value) -> bool {
         match value {
             Value::Nil => false,
             Value::Bool(v) => *v,
@@ -99,50 +560,650 @@ impl Interpreter {
         }
     }
 
-    fn check_number_operand(
+    fn check_number_operands(
         &self,
         operator: &crate::token::Token,
-        operand: &Value,
-    ) -> Result<OrderedFloat<f64>, RuntimeError> {
-        match operand {
-            Value::Number(n) => Ok(*n),
+        left: &Value,
+        right: &Value,
+    ) -> Result<Operands, RuntimeError> {
+        let float = |value: &Value| -> OrderedFloat<f64> {
+            match value {
+                Value::Number(n) => *n,
+                Value::Integer(n) => (*n as f64).into(),
+                Value::BigInt(n) => n.to_f64().unwrap_or(f64::NAN).into(),
+                _ => unreachable!("only numbers are promoted"),
+            }
+        };
+        let big = |value: &Value| -> BigInt {
+            match value {
+                Value::Integer(n) => BigInt::from(*n),
+                Value::BigInt(n) => (**n).clone(),
+                _ => unreachable!("only integers are promoted"),
+            }
+        };
+        match (left, right) {
+            (Value::Integer(l), Value::Integer(r)) => Ok(Operands::Integers(*l, *r)),
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(_) | Value::BigInt(_)) => {
+                Ok(Operands::BigInts(big(left), big(right)))
+            }
+            (
+                Value::Number(_) | Value::Integer(_) | Value::BigInt(_),
+                Value::Number(_) | Value::Integer(_) | Value::BigInt(_),
+            ) => Ok(Operands::Floats(float(left), float(right))),
             _ => Err(RuntimeError::new(
                 operator.clone(),
-                "Operand must be a number.".into(),
+                "Operands must be numbers.".into(),
             )),
         }
     }
-    fn check_number_operands(
+
+    fn compare(
         &self,
-        operator: &crate::token::Token,
+        operator: &Token,
         left: &Value,
         right: &Value,
-    ) -> Result<(OrderedFloat<f64>, OrderedFloat<f64>), RuntimeError> {
-        match (left, right) {
-            (Value::Number(l), Value::Number(r)) => Ok((*l, *r)),
-            _ => Err(RuntimeError::new(
+    ) -> Result<Ordering, RuntimeError> {
+        Ok(match self.check_number_operands(operator, left, right)? {
+            Operands::Integers(l, r) => l.cmp(&r),
+            Operands::BigInts(l, r) => l.cmp(&r),
+            Operands::Floats(l, r) => l.cmp(&r),
+        })
+    }
+
+    /// Applies an arithmetic operator. Integer results that don't fit in an `i64` are an
+    /// error rather than wrapping around, and `/` always divides exactly, giving a float,
+    /// whether its operands are integers or big integers.
+    fn arithmetic(
+        &self,
+        operator: &Token,
+        left: &Value,
+        right: &Value,
+    ) -> Result<Value, RuntimeError> {
+        let (l, r) = match self.check_number_operands(operator, left, right)? {
+            Operands::Integers(l, r) => (l, r),
+            Operands::BigInts(l, r) if operator.ttype == TokenType::Slash => {
+                let l = l.to_f64().unwrap_or(f64::NAN);
+                let r = r.to_f64().unwrap_or(f64::NAN);
+                return Ok(Value::Number((l / r).into()));
+            }
+            Operands::BigInts(l, r) => {
+                let division = matches!(operator.ttype, TokenType::Percent | TokenType::TildeSlash);
+                if division && r.is_zero() {
+                    return Err(RuntimeError::new(
+                        operator.clone(),
+                        "Division by zero.".into(),
+                    ));
+                }
+                return Ok(Value::from(match operator.ttype {
+                    TokenType::Minus => l - r,
+                    TokenType::Percent => l % r,
+                    TokenType::Plus => l + r,
+                    TokenType::Star => l * r,
+                    TokenType::TildeSlash => l / r,
+                    _ => unreachable!("not an arithmetic operator"),
+                }));
+            }
+            Operands::Floats(l, r) => {
+                return Ok(Value::Number(match operator.ttype {
+                    TokenType::Minus => l - r,
+                    TokenType::Percent => l % r,
+                    TokenType::Plus => l + r,
+                    TokenType::Slash => l / r,
+                    TokenType::Star => l * r,
+                    TokenType::TildeSlash => (l / r).trunc().into(),
+                    _ => unreachable!("not an arithmetic operator"),
+                }))
+            }
+        };
+        if r == 0 && matches!(operator.ttype, TokenType::Percent | TokenType::TildeSlash) {
+            return Err(RuntimeError::new(
                 operator.clone(),
-                "Operands must be numbers.".into(),
+                "Division by zero.".into(),
+            ));
+        }
+        let result = match operator.ttype {
+            TokenType::Minus => l.checked_sub(r),
+            TokenType::Percent => l.checked_rem(r),
+            TokenType::Plus => l.checked_add(r),
+            TokenType::Slash => return Ok(Value::Number((l as f64 / r as f64).into())),
+            TokenType::Star => l.checked_mul(r),
+            TokenType::TildeSlash => l.checked_div(r),
+            _ => unreachable!("not an arithmetic operator"),
+        };
+        result
+            .map(Value::Integer)
+            .ok_or_else(|| RuntimeError::new(operator.clone(), "Integer overflow.".into()))
+    }
+
+    /// Raises `left` to the power of `right`. A negative integer exponent gives a float.
+    fn power(&self, operator: &Token, left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+        let overflow = || RuntimeError::new(operator.clone(), "Integer overflow.".into());
+        match self.check_number_operands(operator, left, right)? {
+            Operands::Integers(l, r) if r >= 0 => u32::try_from(r)
+                .ok()
+                .and_then(|r| l.checked_pow(r))
+                .map(Value::Integer)
+                .ok_or_else(overflow),
+            Operands::BigInts(l, r) if !r.is_negative() => {
+                let r = r.to_u32().ok_or_else(overflow)?;
+                Ok(Value::from(Pow::pow(&l, r)))
+            }
+            Operands::Integers(l, r) => Ok(Value::Number((l as f64).powf(r as f64).into())),
+            Operands::BigInts(l, r) => {
+                let l = l.to_f64().unwrap_or(f64::NAN);
+                let r = r.to_f64().unwrap_or(f64::NAN);
+                Ok(Value::Number(l.powf(r).into()))
+            }
+            Operands::Floats(l, r) => Ok(Value::Number(l.powf(*r).into())),
+        }
+    }
+
+    /// Applies a bitwise operator, which only takes integers. Shifting left past the range
+    /// of an `i64` is an overflow, like the arithmetic operators.
+    fn bitwise(
+        &self,
+        operator: &Token,
+        left: &Value,
+        right: &Value,
+    ) -> Result<Value, RuntimeError> {
+        let operands = match (left, right) {
+            (Value::Integer(_) | Value::BigInt(_), Value::Integer(_) | Value::BigInt(_)) => {
+                self.check_number_operands(operator, left, right)?
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be integers.".into(),
+                ))
+            }
+        };
+        let shift = matches!(
+            operator.ttype,
+            TokenType::GreaterGreater | TokenType::LessLess
+        );
+        let negative_shift = match &operands {
+            Operands::Integers(_, r) => *r < 0,
+            Operands::BigInts(_, r) => r.is_negative(),
+            Operands::Floats(..) => unreachable!("operands are integers"),
+        };
+        if shift && negative_shift {
+            return Err(RuntimeError::new(
+                operator.clone(),
+                "Shift count must not be negative.".into(),
+            ));
+        }
+
+        match operands {
+            Operands::Integers(l, r) => {
+                let result = match operator.ttype {
+                    TokenType::Ampersand => Some(l & r),
+                    TokenType::Caret => Some(l ^ r),
+                    TokenType::Pipe => Some(l | r),
+                    TokenType::GreaterGreater => Some(l >> r.min(63)),
+                    TokenType::LessLess if l == 0 => Some(0),
+                    // shifting back has to give the original value, or bits were lost
+                    TokenType::LessLess => u32::try_from(r)
+                        .ok()
+                        .and_then(|r| l.checked_shl(r))
+                        .filter(|shifted| shifted >> r == l),
+                    _ => unreachable!("not a bitwise operator"),
+                };
+                result
+                    .map(Value::Integer)
+                    .ok_or_else(|| RuntimeError::new(operator.clone(), "Integer overflow.".into()))
+            }
+            Operands::BigInts(l, r) => {
+                let count = || {
+                    r.to_usize().ok_or_else(|| {
+                        RuntimeError::new(operator.clone(), "Shift count is too large.".into())
+                    })
+                };
+                Ok(Value::from(match operator.ttype {
+                    TokenType::Ampersand => &l & &r,
+                    TokenType::Caret => &l ^ &r,
+                    TokenType::Pipe => &l | &r,
+                    TokenType::GreaterGreater => &l >> count()?,
+                    TokenType::LessLess => &l << count()?,
+                    _ => unreachable!("not a bitwise operator"),
+                }))
+            }
+            Operands::Floats(..) => unreachable!("operands are integers"),
+        }
+    }
+
+    /// Calls the special method `name` of `instance` for `operator`, or returns `None` if
+    /// its class doesn't define one.
+    fn call_operator_method(
+        &mut self,
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &str,
+        operator: &Token,
+        arguments: &[Value],
+    ) -> Option<Result<Value, RuntimeError>> {
+        let method = instance.borrow().class().find_method(name)?.clone();
+        if !method.arity().accepts(arguments.len()) {
+            return Some(Err(RuntimeError::new(
+                operator.clone(),
+                format!(
+                    "Method '{}' must take {} to overload '{}'.",
+                    name,
+                    if arguments.is_empty() {
+                        "no arguments"
+                    } else {
+                        "one argument"
+                    },
+                    operator.lexeme
+                ),
+            )));
+        }
+        let method = Value::LoxFn(method.bind(instance.clone()));
+        Some(self.call(&method, operator, arguments))
+    }
+
+    /// The method `name` of `instance` bound to it, if its class defines one. Methods used
+    /// by the iterator protocol take no arguments.
+    fn iteration_method(
+        &mut self,
+        keyword: &Token,
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &str,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let method = match instance.borrow().class().find_method(name) {
+            Some(method) => method.clone(),
+            None => return Ok(None),
+        };
+        if !method.arity().accepts(0) {
+            return Err(RuntimeError::new(
+                keyword.clone(),
+                format!("Method '{}' must take no arguments to be iterated.", name),
+            ));
+        }
+        Ok(Some(Value::LoxFn(method.bind(instance.clone()))))
+    }
+
+    /// Starts iterating over `value` for the `for`-`in` loop at `keyword`.
+    ///
+    /// An instance whose class defines `iter()` is iterated through the iterator that method
+    /// returns. Anything else must be an iterator itself: a string, which yields its
+    /// characters, a tuple, which yields its elements, a generator, which yields what its
+    /// body does, or an instance whose `next()` method returns each element in turn and nil
+    /// once there are no more.
+    pub fn iterate(&mut self, keyword: &Token, value: Value) -> Result<Iteration, RuntimeError> {
+        if let Value::LoxInstance(instance) = &value {
+            if let Some(iter) = self.iteration_method(keyword, instance, "iter")? {
+                let iterator = self.call(&iter, keyword, &[])?;
+                return self.iterator(keyword, iterator);
+            }
+        }
+        self.iterator(keyword, value)
+    }
+
+    fn iterator(&mut self, keyword: &Token, value: Value) -> Result<Iteration, RuntimeError> {
+        match &value {
+            Value::String(string) => return Ok(Iteration::Chars(string.clone(), 0)),
+            Value::Generator(generator) => return Ok(Iteration::Generator(generator.clone())),
+            Value::Tuple(elements) => return Ok(Iteration::Elements(elements.clone(), 0)),
+            Value::LoxInstance(instance) => {
+                if let Some(next) = self.iteration_method(keyword, instance, "next")? {
+                    return Ok(Iteration::Next(next));
+                }
+            }
+            _ => {}
+        }
+        Err(RuntimeError::new(
+            keyword.clone(),
+            format!(
+                "Can't iterate over {}. Define 'iter()' or 'next()' to make it iterable.",
+                self.stringify(&value)
+            ),
+        ))
+    }
+
+    /// The next element of `iteration`, or `None` once it is exhausted.
+    pub fn next_element(
+        &mut self,
+        keyword: &Token,
+        iteration: &mut Iteration,
+    ) -> Result<Option<Value>, RuntimeError> {
+        match iteration {
+            Iteration::Chars(string, offset) => Ok(string[*offset..].chars().next().map(|c| {
+                *offset += c.len_utf8();
+                Value::from(c.to_string())
+            })),
+            Iteration::Elements(elements, index) => {
+                let element = elements.get(*index).cloned();
+                *index += 1;
+                Ok(element)
+            }
+            Iteration::Next(next) => match self.call(next, keyword, &[])? {
+                Value::Nil => Ok(None),
+                element => Ok(Some(element)),
+            },
+            Iteration::Generator(generator) => {
+                let element = LoxGenerator::resume(generator, self, Value::Nil)
+                    .map_err(|e| at_token(e, keyword))?;
+                Ok((!generator.borrow().is_done()).then_some(element))
+            }
+        }
+    }
+
+    /// Methods a class gets from the traits it uses, leaving out those it declares itself.
+    /// Two traits defining a method the class doesn't is an error, reported at the second.
+    fn mix_in(
+        traits: &[(Token, LoxTrait)],
+        superclass: Option<&LoxClass>,
+        methods: &[Stmt],
+    ) -> Result<HashMap<String, LoxFunction>, RuntimeError> {
+        let declared: Vec<&str> = methods
+            .iter()
+            .filter_map(|method| match method {
+                Stmt::Function { declaration } => Some(declaration.name.lexeme.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let mut mixed_in = HashMap::new();
+        let mut owners: HashMap<&str, &str> = HashMap::new();
+        for (token, used) in traits {
+            let mut functions = used.methods_for(superclass);
+            for name in used.method_names() {
+                if declared.contains(&name) {
+                    continue;
+                }
+                if let Some(owner) = owners.insert(name, used.name()) {
+                    return Err(RuntimeError::new(
+                        token.clone(),
+                        format!(
+                            "Traits '{}' and '{}' both define '{}'.",
+                            owner,
+                            used.name(),
+                            name
+                        ),
+                    ));
+                }
+                mixed_in.insert(name.to_owned(), functions.remove(name).unwrap());
+            }
+        }
+        Ok(mixed_in)
+    }
+
+    /// Picks the first arm of a `match` whose pattern matches `value` and whose guard, if
+    /// it has one, is truthy. Returns its index along with a scope holding the variables
+    /// its pattern bound, which is also the scope its guard was evaluated in.
+    pub fn select_arm(
+        &mut self,
+        keyword: &Token,
+        value: &Value,
+        arms: &[MatchArm],
+    ) -> Result<(usize, Rc<RefCell<Environment>>), RuntimeError> {
+        for (index, arm) in arms.iter().enumerate() {
+            let mut bindings = Vec::new();
+            let mut selected = self.match_pattern(&arm.pattern, value, &mut bindings)?;
+            let mut environment = Environment::with_enclosing(self.environment.clone());
+            for (name, value) in bindings {
+                environment.define(name, value);
+            }
+            let environment = gc::alloc_environment(environment);
+            if let (true, Some(guard)) = (selected, &arm.guard) {
+                let previous = self.set_environment(environment.clone());
+                let guard = self.evaluate(guard);
+                self.environment = previous;
+                selected = self.is_truthy(&guard?);
+            }
+            self.branch(arm.pattern.token(), if selected { 0 } else { 1 });
+            if selected {
+                return Ok((index, environment));
+            }
+        }
+        Err(RuntimeError::new(
+            keyword.clone(),
+            format!("No match arm matches {}.", self.stringify(value)),
+        ))
+    }
+
+    /// Whether `value` matches `pattern`, collecting the variables it binds if so.
+    fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: &Value,
+        bindings: &mut Vec<(String, Value)>,
+    ) -> Result<bool, RuntimeError> {
+        match pattern {
+            Pattern::Wildcard(_) => Ok(true),
+            Pattern::Literal {
+                token,
+                value: literal,
+            } => {
+                let operator = Token::new(
+                    TokenType::EqualEqual,
+                    "==",
+                    &TokenLiteral::Nil,
+                    token.line,
+                    token.cursor,
+                );
+                self.equal(value, &operator, &Value::from(literal.clone()))
+            }
+            Pattern::Binding(name) => {
+                bindings.push((name.lexeme.clone(), value.clone()));
+                Ok(true)
+            }
+            Pattern::Alternatives(alternatives) => {
+                for alternative in alternatives {
+                    if self.match_pattern(alternative, value, bindings)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Pattern::Instance { class, fields } => {
+                let Value::LoxClass(class) = self.evaluate(class)? else {
+                    return Err(RuntimeError::new(
+                        pattern.token().clone(),
+                        "Can only match instances of classes.".into(),
+                    ));
+                };
+                let Value::LoxInstance(instance) = value else {
+                    return Ok(false);
+                };
+                if !instance.borrow().class().is_subclass_of(&class) {
+                    return Ok(false);
+                }
+                for (name, pattern) in fields {
+                    let field = instance.borrow().field(&name.lexeme);
+                    match field {
+                        Some(field) if self.match_pattern(pattern, &field, bindings)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Applies a binary operator to values that were already evaluated.
+    ///
+    /// If an operand is an instance, the operator calls the special method its class
+    /// defines for it instead: `__add__` on the left operand for `+`, or else `__radd__`
+    /// on the right one. Without either, adding an instance to a string concatenates them
+    /// and every other operator is an error. `==` and `!=` are handled by `equal`.
+    fn binary(
+        &mut self,
+        left: &Value,
+        operator: &Token,
+        right: &Value,
+    ) -> Result<Value, RuntimeError> {
+        match operator.ttype {
+            TokenType::BangEqual => return Ok(Value::from(!self.equal(left, operator, right)?)),
+            TokenType::EqualEqual => return Ok(Value::from(self.equal(left, operator, right)?)),
+            _ => {}
+        }
+
+        // the left operand's method comes first, then the right operand's reflected one
+        let method = match left {
+            Value::LoxInstance(instance) => {
+                operator_method(operator.ttype).map(|name| (instance, name, right))
+            }
+            _ => None,
+        };
+        let reflected = match right {
+            Value::LoxInstance(instance) => {
+                reflected_operator_method(operator.ttype).map(|name| (instance, name, left))
+            }
+            _ => None,
+        };
+        for &(instance, name, other) in method.iter().chain(reflected.iter()) {
+            let arguments = slice::from_ref(other);
+            if let Some(result) = self.call_operator_method(instance, name, operator, arguments) {
+                return result;
+            }
+        }
+        if let Some((instance, name, _)) = method.or(reflected) {
+            let concatenation =
+                operator.ttype == TokenType::Plus && matches!(left, Value::String(_));
+            if !concatenation {
+                return Err(operator_not_defined(operator, instance, name));
+            }
+        }
+
+        match operator.ttype {
+            TokenType::Greater => Ok(Value::from(
+                self.compare(operator, left, right)? == Ordering::Greater,
+            )),
+            TokenType::GreaterEqual => Ok(Value::from(
+                self.compare(operator, left, right)? != Ordering::Less,
             )),
+            TokenType::Less => Ok(Value::from(
+                self.compare(operator, left, right)? == Ordering::Less,
+            )),
+            TokenType::LessEqual => Ok(Value::from(
+                self.compare(operator, left, right)? != Ordering::Greater,
+            )),
+            TokenType::Plus => match (left, right) {
+                (Value::String(left), Value::String(right)) => {
+                    Ok(Value::from(format!("{}{}", left, right)))
+                }
+                (Value::String(left), _) => {
+                    Ok(Value::from(format!("{}{}", left, self.display(right)?)))
+                }
+                (
+                    Value::Number(_) | Value::Integer(_) | Value::BigInt(_),
+                    Value::Number(_) | Value::Integer(_) | Value::BigInt(_),
+                ) => self.arithmetic(operator, left, right),
+                _ => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be two numbers or two strings.".into(),
+                )),
+            },
+            TokenType::Minus
+            | TokenType::Percent
+            | TokenType::Slash
+            | TokenType::Star
+            | TokenType::TildeSlash => self.arithmetic(operator, left, right),
+            TokenType::StarStar => self.power(operator, left, right),
+            TokenType::Ampersand
+            | TokenType::Caret
+            | TokenType::GreaterGreater
+            | TokenType::LessLess
+            | TokenType::Pipe => self.bitwise(operator, left, right),
+            _ => panic!("unreachable"),
         }
     }
 
-    fn stringify(&self, value: &Value) -> String {
-        match value {
-            Value::Nil => "nil".into(),
-            Value::Number(v) => {
-                let mut text = v.to_string();
-                if text.ends_with(".0") {
-                    text = text[..text.len() - 2].into();
+    /// Whether `left` and `right` are equal, as `==` tests it at `operator`.
+    ///
+    /// If the left operand is an instance whose class defines `__eq__`, that method decides,
+    /// and otherwise the right operand's does, called with the left one. Tuples are equal if
+    /// their elements are, compared the same way. Everything else uses `Value::eq`.
+    pub fn equal(
+        &mut self,
+        left: &Value,
+        operator: &Token,
+        right: &Value,
+    ) -> Result<bool, RuntimeError> {
+        for (value, other) in [(left, right), (right, left)] {
+            if let Value::LoxInstance(instance) = value {
+                let arguments = slice::from_ref(other);
+                if let Some(result) =
+                    self.call_operator_method(instance, "__eq__", operator, arguments)
+                {
+                    return Ok(self.is_truthy(&result?));
+                }
+            }
+        }
+        if let (Value::Tuple(left), Value::Tuple(right)) = (left, right) {
+            if left.len() != right.len() {
+                return Ok(false);
+            }
+            for (left, right) in left.iter().zip(right.iter()) {
+                if !self.equal(left, operator, right)? {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+        Ok(left == right)
+    }
+
+    /// Converts a value to the text `print` shows, which for an instance whose class
+    /// defines `__str__` is what that method returns. `stringify` gives the plain text
+    /// without running any Lox code.
+    pub fn display(&mut self, value: &Value) -> Result<String, RuntimeError> {
+        let instance = match value {
+            Value::LoxInstance(instance) => instance,
+            Value::Tuple(elements) => {
+                let mut displayed = Vec::new();
+                for element in elements.iter() {
+                    displayed.push(self.display(element)?);
                 }
-                text
+                return Ok(format!("({})", displayed.join(", ")));
             }
-            Value::String(v) => v.clone(),
+            _ => return Ok(self.stringify(value)),
+        };
+        let method = match instance.borrow().class().find_method("__str__") {
+            Some(method) => method.bind(instance.clone()),
+            None => return Ok(self.stringify(value)),
+        };
+        let token = Token::new(
+            TokenType::Identifier,
+            "__str__",
+            &TokenLiteral::Nil,
+            method.line(),
+            0,
+        );
+        if !method.arity().accepts(0) {
+            return Err(RuntimeError::new(
+                token,
+                "Method '__str__' must not take any arguments.".into(),
+            ));
+        }
+        match self.call(&Value::LoxFn(method), &token, &[])? {
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err(RuntimeError::new(
+                token,
+                "Method '__str__' must return a string.".into(),
+            )),
+        }
+    }
+
+    pub fn stringify(&self, value: &Value) -> String {
+        match value {
+            Value::Nil => "nil".into(),
+            Value::Number(v) => v.to_string(),
+            Value::Integer(v) => v.to_string(),
+            Value::BigInt(v) => v.to_string(),
+            Value::String(v) => v.to_string(),
             Value::Bool(v) => v.to_string(),
             Value::NativeFn(v) => v.string_repr(),
+            Value::NativeMethod(v) => v.string_repr(),
             Value::LoxFn(v) => v.string_repr(),
             Value::LoxClass(v) => v.to_string(),
+            Value::LoxTrait(v) => v.to_string(),
             Value::LoxInstance(v) => v.borrow().to_string(),
+            Value::Generator(v) => v.borrow().to_string(),
+            Value::Tuple(v) => {
+                let elements: Vec<String> = v.iter().map(|v| self.stringify(v)).collect();
+                format!("({})", elements.join(", "))
+            }
         }
     }
 }
@@ -152,19 +1213,21 @@ impl StmtVisitor for Interpreter {
 
     fn visit_block(&mut self, statements: &Vec<Option<Stmt>>) -> Self::Output {
         let env = Environment::with_enclosing(self.environment.clone());
-        self.execute_block(statements, Rc::new(RefCell::new(env)))
+        self.execute_block(statements, gc::alloc_environment(env))
     }
 
     fn visit_class(
         &mut self,
         name: &Token,
         superclass: &Option<Expr>,
+        traits: &[Expr],
         methods: &Vec<Stmt>,
     ) -> Self::Output {
         let mut superclass_cls = None;
         if let Some(
             superclass @ Expr::Variable {
                 name: superclass_name,
+                ..
             },
         ) = superclass
         {
@@ -181,39 +1244,55 @@ impl StmtVisitor for Interpreter {
             }
         }
 
-        self.environment
-            .borrow_mut()
-            .define(name.lexeme.clone(), Value::Nil);
+        let mut trait_values = Vec::new();
+        for used in traits {
+            let Expr::Variable {
+                name: trait_name, ..
+            } = used
+            else {
+                unreachable!("classes name the traits they use");
+            };
+            match self.evaluate(used)? {
+                Value::LoxTrait(value) => trait_values.push((trait_name.clone(), value)),
+                _ => {
+                    return Err(RuntimeError::new(
+                        trait_name.clone(),
+                        "Can only mix in traits.".into(),
+                    ));
+                }
+            }
+        }
+        let mut method_values = Self::mix_in(&trait_values, superclass_cls.as_deref(), methods)?;
+
+        self.environment.borrow_mut().declare(name, Value::Nil)?;
 
         let mut is_subclass = false;
         if let Some(superclass) = &superclass_cls {
             is_subclass = true;
             let value = Value::LoxClass(*superclass.clone());
             self.environment =
-                RefCell::new(Environment::with_enclosing(self.environment.clone())).into();
+                gc::alloc_environment(Environment::with_enclosing(self.environment.clone()));
             self.environment
                 .borrow_mut()
                 .define("super".to_owned(), value);
         }
 
-        let mut method_values = HashMap::new();
         for method in methods {
             match method {
-                Stmt::Function { name, params, body } => {
+                Stmt::Function { declaration } => {
                     let function = LoxFunction::new(
-                        name,
-                        params,
-                        body,
+                        declaration.clone(),
                         self.environment.clone(),
-                        name.lexeme == "init",
+                        declaration.name.lexeme == "init",
                     );
-                    method_values.insert(name.lexeme.clone(), function);
+                    method_values.insert(declaration.name.lexeme.clone(), function);
                 }
                 _ => unreachable!(),
             }
         }
 
-        let class = LoxClass::new(&name.lexeme, superclass_cls, method_values).into();
+        let trait_values = trait_values.into_iter().map(|(_, value)| value).collect();
+        let class = LoxClass::new(&name.lexeme, superclass_cls, trait_values, method_values).into();
         if is_subclass {
             let outer = self
                 .environment
@@ -224,50 +1303,124 @@ impl StmtVisitor for Interpreter {
                 .clone();
             self.environment = outer;
         }
-        self.environment.borrow_mut().assign(name, class)?;
+        let mut environment = self.environment.borrow_mut();
+        environment.assign(name, class)?;
+        if self.strict {
+            environment.mark_const(name.lexeme.clone());
+        }
         Ok(())
     }
 
+    fn visit_const(
+        &mut self,
+        name: &Token,
+        _annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output {
+        let value = self.evaluate(initializer)?;
+        self.environment.borrow_mut().declare_const(name, value)
+    }
+
     fn visit_expression(&mut self, expression: &Expr) -> Self::Output {
         self.evaluate(expression)?;
         Ok(())
     }
 
-    fn visit_function(
+    fn visit_for_in(
         &mut self,
+        keyword: &Token,
         name: &Token,
-        params: &[Token],
-        body: &[Option<Stmt>],
+        iterable: &Expr,
+        body: &Stmt,
     ) -> Self::Output {
-        let function = LoxFunction::new(name, params, body, self.environment.clone(), false);
-        self.environment
-            .borrow_mut()
-            .define(name.lexeme.clone(), Value::LoxFn(function));
+        let iterable = self.evaluate(iterable)?;
+        let mut iteration = self.iterate(keyword, iterable)?;
+        while let Some(element) = self.next_element(keyword, &mut iteration)? {
+            self.branch(keyword, 0);
+            // a fresh scope per element, so closures capture the element they were made for
+            let mut environment = Environment::with_enclosing(self.environment.clone());
+            environment.define(name.lexeme.clone(), element);
+            self.execute_in(body, gc::alloc_environment(environment))?;
+        }
+        self.branch(keyword, 1);
         Ok(())
     }
 
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output {
+        let function = Value::LoxFn(LoxFunction::new(
+            declaration.clone(),
+            self.environment.clone(),
+            false,
+        ));
+        let name = &declaration.name;
+        if self.strict {
+            self.environment.borrow_mut().declare_const(name, function)
+        } else {
+            self.environment.borrow_mut().declare(name, function)
+        }
+    }
+
     fn visit_if(
         &mut self,
+        keyword: &Token,
         condition: &Expr,
         then_branch: &Stmt,
         else_branch: &Option<Stmt>,
     ) -> Self::Output {
         let cond_res = self.evaluate(condition)?;
         if self.is_truthy(&cond_res) {
+            self.branch(keyword, 0);
             self.execute(then_branch)?;
-        } else if else_branch.is_some() {
-            self.execute(else_branch.as_ref().unwrap())?;
+        } else {
+            self.branch(keyword, 1);
+            if let Some(else_branch) = else_branch {
+                self.execute(else_branch)?;
+            }
         }
         Ok(())
     }
 
-    fn visit_print(&mut self, expression: &Expr) -> Self::Output {
+    fn visit_match(&mut self, keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output {
+        let subject = self.evaluate(subject)?;
+        let (index, environment) = self.select_arm(keyword, &subject, arms)?;
+        self.execute_in(&arms[index].body, environment)
+    }
+
+    fn visit_print(&mut self, _keyword: &Token, expression: &Expr) -> Self::Output {
         let value = self.evaluate(expression)?;
-        println!("{}", self.stringify(&value));
+        println!("{}", self.display(&value)?);
         Ok(())
     }
 
     fn visit_return(&mut self, keyword: &Token, value: &Option<Rc<Expr>>) -> Self::Output {
+        if let Some(Expr::Call {
+            id,
+            callee,
+            paren,
+            arguments,
+            keywords,
+        }) = value.as_deref()
+        {
+            // hooks expect every call to return before its caller does
+            if self.tail_calls.contains(id) && self.hooks.is_empty() {
+                let (callee, arguments, keywords) =
+                    self.evaluate_call(callee, paren, arguments, keywords)?;
+                if let Value::LoxFn(function) = callee {
+                    return Err(RuntimeError::new_tail_call(
+                        keyword.clone(),
+                        function,
+                        arguments,
+                        keywords,
+                    ));
+                }
+                let value = self.call_with_keywords(&callee, paren, &arguments, &keywords)?;
+                return Err(RuntimeError::new_return(
+                    keyword.clone(),
+                    "return".into(),
+                    Some(value),
+                ));
+            }
+        }
         if let Some(value) = value {
             let value = self.evaluate(value)?;
             Err(RuntimeError::new_return(
@@ -284,7 +1437,28 @@ impl StmtVisitor for Interpreter {
         }
     }
 
-    fn visit_var(&mut self, name: &Token, initializer: &Option<Expr>) -> Self::Output {
+    fn visit_trait(&mut self, name: &Token, methods: &[Stmt]) -> Self::Output {
+        let mut declarations = HashMap::new();
+        for method in methods {
+            if let Stmt::Function { declaration } = method {
+                declarations.insert(declaration.name.lexeme.clone(), declaration.clone());
+            }
+        }
+        let value = LoxTrait::new(&name.lexeme, declarations, self.environment.clone()).into();
+        let mut environment = self.environment.borrow_mut();
+        if self.strict {
+            environment.declare_const(name, value)
+        } else {
+            environment.declare(name, value)
+        }
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        _annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output {
         let mut value = Value::Nil;
         match initializer {
             Some(expr) => {
@@ -292,19 +1466,28 @@ impl StmtVisitor for Interpreter {
             }
             _ => {}
         };
-        self.environment
-            .borrow_mut()
-            .define(name.lexeme.clone(), value);
+        self.environment.borrow_mut().declare(name, value)
+    }
+
+    fn visit_var_tuple(&mut self, names: &[Token], initializer: &Expr) -> Self::Output {
+        let value = self.evaluate(initializer)?;
+        let elements = Self::unpack(&names.iter().collect::<Vec<_>>(), &value)?;
+        let mut environment = self.environment.borrow_mut();
+        for (name, element) in names.iter().zip(elements.iter()) {
+            environment.declare(name, element.clone())?;
+        }
         Ok(())
     }
 
-    fn visit_while(&mut self, condition: &Expr, body: &Stmt) -> Self::Output {
+    fn visit_while(&mut self, keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output {
         while {
             let cond_res = self.evaluate(condition)?;
             self.is_truthy(&cond_res)
         } {
+            self.branch(keyword, 0);
             self.execute(body)?;
         }
+        self.branch(keyword, 1);
         Ok(())
     }
 }
@@ -312,145 +1495,153 @@ impl StmtVisitor for Interpreter {
 impl ExprVisitor for Interpreter {
     type Output = Result<Value, RuntimeError>;
 
-    fn visit_assign(&mut self, name: &Token, value_expr: &Expr) -> Self::Output {
-        let value = self.evaluate(value_expr)?;
+    fn visit_assign(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        operator: Option<&Token>,
+        value_expr: &Expr,
+    ) -> Self::Output {
+        let value = match operator {
+            Some(operator) => {
+                let current = self.look_up_variable(id, name)?;
+                let value = self.evaluate(value_expr)?;
+                self.binary(&current, operator, &value)?
+            }
+            None => self.evaluate(value_expr)?,
+        };
 
-        let expr = Expr::new_assign(name.clone(), value_expr.clone()); // hack
-        let distance = self.locals.get(&expr);
-        if let Some(distance) = distance {
-            self.environment
-                .borrow_mut()
-                .assign_at(*distance, name, &value);
-        } else {
-            self.globals.borrow_mut().assign(name, value.clone())?;
-        }
+        self.assign_variable(id, name, &value)?;
+        Ok(value)
+    }
 
+    fn visit_assign_tuple(&mut self, targets: &[(ExprId, Token)], value: &Expr) -> Self::Output {
+        let value = self.evaluate(value)?;
+        let names: Vec<&Token> = targets.iter().map(|(_, name)| name).collect();
+        let elements = Self::unpack(&names, &value)?;
+        for ((id, name), element) in targets.iter().zip(elements.iter()) {
+            self.assign_variable(*id, name, element)?;
+        }
         Ok(value)
     }
 
     fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
         let left = self.evaluate(left)?;
         let right = self.evaluate(right)?;
-
-        match operator.ttype {
-            TokenType::BangEqual => Ok(Value::from(left != right)),
-            TokenType::EqualEqual => Ok(Value::from(left == right)),
-            TokenType::Greater => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left > right))
-            }
-            TokenType::GreaterEqual => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left >= right))
-            }
-            TokenType::Less => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left < right))
-            }
-            TokenType::LessEqual => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left <= right))
-            }
-            TokenType::Minus => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left - right))
-            }
-            TokenType::Plus => match (&left, &right) {
-                (Value::Number(left), Value::Number(right)) => Ok(Value::from(*left + *right)),
-                (Value::String(left), Value::String(right)) => {
-                    Ok(Value::from(format!("{}{}", left, right)))
-                }
-                (Value::String(left), _) => {
-                    Ok(Value::from(format!("{}{}", left, self.stringify(&right))))
-                }
-                _ => Err(RuntimeError::new(
-                    operator.clone(),
-                    "Operands must be two numbers or two strings.".into(),
-                )),
-            },
-            TokenType::Slash => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left / right))
-            }
-            TokenType::Star => {
-                let (left, right) = self.check_number_operands(operator, &left, &right)?;
-                Ok(Value::from(left * right))
-            }
-            _ => panic!("unreachable"),
-        }
+        self.binary(&left, operator, &right)
     }
 
-    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Self::Output {
-        let callee = self.evaluate(callee)?;
-
-        let mut argument_values = Vec::new();
-        for argument in arguments {
-            argument_values.push(self.evaluate(argument)?);
-        }
-
-        let function: &dyn LoxCallable = match &callee {
-            Value::NativeFn(f) => *f,
-            Value::LoxFn(f) => f,
-            Value::LoxClass(f) => f,
-            _ => {
-                return Err(RuntimeError::new(
-                    paren.clone(),
-                    "Can only call functions and classes.".into(),
-                ));
-            }
-        };
+    fn visit_call(
+        &mut self,
+        _id: ExprId,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output {
+        let (callee, arguments, keywords) =
+            self.evaluate_call(callee, paren, arguments, keywords)?;
+        self.call_with_keywords(&callee, paren, &arguments, &keywords)
+    }
 
-        if arguments.len() != function.arity() {
-            return Err(RuntimeError::new(
-                paren.clone(),
-                format!(
-                    "Expected {} arguments but got {}.",
-                    function.arity(),
-                    arguments.len()
-                ),
-            ));
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        let condition = self.evaluate(condition)?;
+        if self.is_truthy(&condition) {
+            self.branch(question, 0);
+            self.evaluate(then_branch)
+        } else {
+            self.branch(question, 1);
+            self.evaluate(else_branch)
         }
-
-        function.call(self, &argument_values)
     }
 
     fn visit_get(&mut self, object: &Expr, name: &Token) -> Self::Output {
         let object = self.evaluate(object)?;
-        if let Value::LoxInstance(instance) = object {
-            return LoxInstance::get(instance, name);
+        match object {
+            Value::LoxInstance(instance) => LoxInstance::get(instance, name),
+            Value::Generator(generator) => LoxGenerator::get(&generator, name),
+            _ => Err(RuntimeError::new(
+                name.clone(),
+                "Only instances have properties.".into(),
+            )),
         }
-        Err(RuntimeError::new(
-            name.clone(),
-            "Only instances have properties.".into(),
-        ))
     }
 
     fn visit_grouping(&mut self, expression: &Expr) -> Self::Output {
         self.evaluate(expression)
     }
 
-    fn visit_literal(&mut self, literal: &TokenLiteral) -> Self::Output {
+    fn visit_literal(&mut self, _token: Option<&Token>, literal: &TokenLiteral) -> Self::Output {
         Ok(Value::from(literal.clone()))
     }
 
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output {
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        Ok(Value::from(values))
+    }
+
     fn visit_unary(
         &mut self,
         operator: &crate::token::Token,
         right: &crate::expr::Expr,
     ) -> Self::Output {
         let right = self.evaluate(right)?;
+        if let Value::LoxInstance(instance) = &right {
+            let name = match operator.ttype {
+                TokenType::Minus => Some("__neg__"),
+                TokenType::Tilde => Some("__invert__"),
+                _ => None,
+            };
+            if let Some(name) = name {
+                return self
+                    .call_operator_method(instance, name, operator, &[])
+                    .unwrap_or_else(|| Err(operator_not_defined(operator, instance, name)));
+            }
+        }
         match operator.ttype {
             TokenType::Bang => Ok(Value::Bool(!self.is_truthy(&right))),
-            TokenType::Minus => {
-                let n = self.check_number_operand(operator, &right)?;
-                Ok(Value::Number(-n))
-            }
+            TokenType::Minus => match right {
+                Value::Number(n) => Ok(Value::Number(-n)),
+                Value::BigInt(n) => Ok(Value::from(-(*n).clone())),
+                Value::Integer(n) => n
+                    .checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| RuntimeError::new(operator.clone(), "Integer overflow.".into())),
+                _ => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operand must be a number.".into(),
+                )),
+            },
+            TokenType::Tilde => match right {
+                Value::Integer(n) => Ok(Value::Integer(!n)),
+                Value::BigInt(n) => Ok(Value::from(!(*n).clone())),
+                _ => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operand must be an integer.".into(),
+                )),
+            },
             _ => panic!("unreachable"),
         }
     }
 
-    fn visit_variable(&mut self, name: &crate::token::Token) -> Self::Output {
-        self.look_up_variable(name, &Expr::new_variable(name.clone()))
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> Self::Output {
+        self.look_up_variable(id, name)
+    }
+
+    fn visit_yield(&mut self, keyword: &Token, _value: Option<&Expr>) -> Self::Output {
+        // generators evaluate the operand themselves and only get here once resumed
+        self.resumed.take().ok_or_else(|| {
+            RuntimeError::new(keyword.clone(), "Can only yield inside a generator.".into())
+        })
     }
 
     fn visit_logical(
@@ -464,21 +1655,37 @@ impl ExprVisitor for Interpreter {
         // check if we can short circuit
         if operator.ttype == TokenType::Or {
             if self.is_truthy(&left) {
+                self.branch(operator, 0);
                 return Ok(left);
             }
         } else {
             if !self.is_truthy(&left) {
+                self.branch(operator, 0);
                 return Ok(left);
             }
         }
 
+        self.branch(operator, 1);
         return self.evaluate(right);
     }
 
-    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) -> Self::Output {
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
         let object = self.evaluate(object)?;
         if let Value::LoxInstance(instance) = object {
-            let value = self.evaluate(value)?;
+            let value = match operator {
+                Some(operator) => {
+                    let current = LoxInstance::get(instance.clone(), name)?;
+                    let value = self.evaluate(value)?;
+                    self.binary(&current, operator, &value)?
+                }
+                None => self.evaluate(value)?,
+            };
             instance.borrow_mut().set(name, &value);
             Ok(value)
         } else {
@@ -489,20 +1696,16 @@ impl ExprVisitor for Interpreter {
         }
     }
 
-    fn visit_super(&mut self, keyword: &Token, method: &Token) -> Self::Output {
-        let expr = Expr::new_super(keyword.clone(), method.clone());
-        let distance = self.locals.get(&expr).unwrap();
-        let superclass = self
-            .environment
-            .borrow()
-            .get_at(*distance, "super")
-            .unwrap();
-        let object = self
-            .environment
-            .borrow()
-            .get_at(distance - 1, "this")
-            .unwrap();
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, method: &Token) -> Self::Output {
+        let (distance, index) = self.locals[&id];
+        let superclass = self.environment.borrow().get_at(distance, index);
+        // `this` is the only variable in the scope just inside the one binding `super`
+        let object = self.environment.borrow().get_at(distance - 1, 0);
         match (superclass, object) {
+            (Value::Nil, _) => Err(RuntimeError::new(
+                keyword.clone(),
+                "Can't use 'super' in a trait method of a class with no superclass.".into(),
+            )),
             (Value::LoxClass(superclass), Value::LoxInstance(object)) => {
                 if let Some(method) = superclass.find_method(&method.lexeme) {
                     return Ok(Value::LoxFn(method.bind(object.clone())));
@@ -517,8 +1720,7 @@ impl ExprVisitor for Interpreter {
         }
     }
 
-    fn visit_this(&mut self, keyword: &Token) -> Self::Output {
-        let expr = Expr::new_this(keyword.clone());
-        self.look_up_variable(keyword, &expr)
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> Self::Output {
+        self.look_up_variable(id, keyword)
     }
 }
@@ -1,17 +1,29 @@
+pub mod ast_printer;
+pub mod coverage;
+pub mod debugger;
 pub mod environment;
 pub mod errors;
 pub mod expr;
+pub mod gc;
+pub mod hook;
 pub mod interpreter;
 pub mod lox;
 pub mod lox_callable;
 pub mod lox_class;
 pub mod lox_function;
+pub mod lox_generator;
 pub mod lox_instance;
+pub mod lox_trait;
 pub mod native_functions;
+pub mod optimizer;
 pub mod parser;
+pub mod pattern;
+pub mod profiler;
 pub mod resolver;
 pub mod scanner;
 pub mod stmt;
+pub mod test_runner;
 pub mod token;
+pub mod type_checker;
 pub mod value;
 pub mod vm;
@@ -9,38 +9,107 @@ use std::{
     process,
 };
 
+use crate::ast_printer::AstPrinter;
+use crate::coverage::Coverage;
+use crate::debugger::Debugger;
 use crate::errors::RuntimeError;
 use crate::interpreter::Interpreter;
+use crate::optimizer::Optimizer;
 use crate::parser::Parser;
+use crate::profiler::Profiler;
 use crate::resolver::Resolver;
 use crate::scanner::Scanner;
+use crate::test_runner::TestRunner;
 use crate::token::{Token, TokenType};
+use crate::type_checker::TypeChecker;
 
 static mut HAD_ERROR: Mutex<bool> = Mutex::new(false);
 static mut HAD_RUNTIME_ERROR: Mutex<bool> = Mutex::new(false);
 
 pub struct Lox;
 
+/// Settings that change how a program is run.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Run the program under the interactive debugger.
+    pub debug: bool,
+    /// Report per-function timings and line counts once the program finishes.
+    pub profile: bool,
+    /// Where to write folded stacks for flamegraph tools while profiling.
+    pub profile_folded: Option<String>,
+    /// Where to write an lcov tracefile of the statements and branches that ran.
+    pub coverage: Option<String>,
+    /// Run the optimizer over the program before it is executed.
+    pub optimize: bool,
+    /// Print the program's syntax tree, after optimization if enabled, instead of running it.
+    pub dump_ast: bool,
+    /// Treat function and class names as constants.
+    pub strict: bool,
+    /// Check type annotations before running the program.
+    pub typecheck: bool,
+}
+
 impl Lox {
     pub fn run_cli() {
-        let mut args = env::args();
-        if args.len() > 2 {
-            println!("usage: rlox [script]");
-            process::exit(64);
-        } else if args.len() == 2 {
-            let _program = args.next().unwrap();
-            let path = args.next().unwrap();
-            println!("RUNNING LOX FILE: {}", path);
-            Self::run_file(&path);
-        } else {
-            println!("RUNNING LOX INTERPRETER");
-            Self::run_prompt();
+        let mut options = Options::default();
+        let mut positional = Vec::new();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--profile" => options.profile = true,
+                "--profile-folded" => {
+                    options.profile = true;
+                    options.profile_folded = Some(args.next().unwrap_or_else(|| Self::usage()));
+                }
+                "--coverage" => {
+                    options.coverage = Some(args.next().unwrap_or_else(|| Self::usage()));
+                }
+                "-O" => options.optimize = true,
+                "--dump-ast" => options.dump_ast = true,
+                "--strict" => options.strict = true,
+                "--typecheck" => options.typecheck = true,
+                _ if arg.starts_with('-') => Self::usage(),
+                _ => positional.push(arg),
+            }
         }
+
+        match positional.as_slice() {
+            [] => {
+                println!("RUNNING LOX INTERPRETER");
+                Self::run_prompt();
+            }
+            [command, dir] if command == "test" => {
+                let passed = TestRunner::new().run(dir);
+                process::exit(if passed { 0 } else { 1 });
+            }
+            [command, path] if command == "debug" => {
+                options.debug = true;
+                Self::run_file(path, &options);
+            }
+            [path] => Self::run_file(path, &options),
+            _ => Self::usage(),
+        }
+    }
+
+    fn usage() -> ! {
+        println!("usage: rlox [options] [script]");
+        println!("       rlox [options] debug <script>");
+        println!("       rlox test <dir>");
+        println!();
+        println!("options:");
+        println!("  --profile               print per-function timings when the script ends");
+        println!("  --profile-folded FILE   also write folded stacks for flamegraph tools");
+        println!("  --coverage FILE         write lcov line and branch coverage to FILE");
+        println!("  -O                      fold constants and remove dead branches first");
+        println!("  --dump-ast              print the syntax tree instead of running it");
+        println!("  --strict                treat function and class names as constants");
+        println!("  --typecheck             check type annotations before running");
+        process::exit(64);
     }
 
-    fn run_file(path: &str) {
+    fn run_file(path: &str, options: &Options) {
         let content = fs::read_to_string(path).unwrap();
-        Self::run(&content);
+        Self::run(&content, path, options);
         if Self::had_error() {
             process::exit(65);
         }
@@ -59,27 +128,66 @@ impl Lox {
             if n_bytes == 0 {
                 break;
             }
-            Self::run(&line);
+            Self::run(&line, "<stdin>", &Options::default());
             Self::set_had_error(false);
         }
     }
 
-    fn run(source: &str) {
+    /// Runs `source`, read from `path`, and returns the interpreter it ran in. Returns
+    /// `None` if the program had a compile error or only had its syntax tree printed.
+    pub(crate) fn run(
+        source: &str,
+        path: &str,
+        options: &Options,
+    ) -> Option<Rc<RefCell<Interpreter>>> {
         let mut scanner = Scanner::new(source);
         let tokens = scanner.scan_tokens();
         let mut parser = Parser::new(&tokens);
         let statement_opts = parser.parse();
         if Self::had_error() {
-            return;
+            return None;
+        }
+
+        if options.typecheck {
+            TypeChecker::new(source).check(&statement_opts);
+            if Self::had_error() {
+                return None;
+            }
         }
 
         let interpreter = Rc::new(RefCell::new(Interpreter::new()));
+        interpreter.borrow_mut().set_strict(options.strict);
         let mut resolver = Resolver::new(interpreter.clone());
+        resolver.set_strict(options.strict);
         resolver.resolve_stmt_opts(&statement_opts);
         if Self::had_error() {
-            return;
+            return None;
+        }
+
+        let statement_opts = if options.optimize {
+            Optimizer::new().optimize(&statement_opts)
+        } else {
+            statement_opts
+        };
+        if options.dump_ast {
+            print!("{}", AstPrinter::new().print(&statement_opts));
+            return None;
         }
 
+        let mut interpreter_mut = interpreter.borrow_mut();
+        if options.debug {
+            interpreter_mut.add_hook(Box::new(Debugger::new(source)));
+        }
+        if options.profile {
+            let profiler = Profiler::new(options.profile_folded.clone());
+            interpreter_mut.add_hook(Box::new(profiler));
+        }
+        if let Some(lcov_path) = &options.coverage {
+            let coverage = Coverage::new(path, lcov_path, &statement_opts);
+            interpreter_mut.add_hook(Box::new(coverage));
+        }
+        drop(interpreter_mut);
+
         let mut statements = Vec::new();
         for stmt_opt in statement_opts {
             statements.push(
@@ -88,6 +196,7 @@ impl Lox {
         }
 
         interpreter.borrow_mut().interpret(&statements);
+        Some(interpreter)
     }
 
     pub fn error_on_line(line: usize, message: &str) {
@@ -102,8 +211,25 @@ impl Lox {
         }
     }
 
+    /// Reports an error at `token`, which starts at `column` of its line.
+    pub fn error_at_column(token: &Token, column: usize, message: &str) {
+        eprintln!(
+            "[line {}, column {}] Error at '{}': {}",
+            token.line, column, token.lexeme, message
+        );
+        Self::set_had_error(true);
+    }
+
+    /// Reports something suspicious that doesn't stop the program from running.
+    pub fn warning_on_token(token: &Token, message: &str) {
+        eprintln!(
+            "[line {}] Warning at '{}': {}",
+            token.line, token.lexeme, message
+        );
+    }
+
     pub fn runtime_error(error: RuntimeError) {
-        println!("[line {}] Error: {}", error.token.line, error.msg);
+        eprintln!("[line {}] Error: {}", error.token.line, error.msg);
         Self::set_had_runtime_error(true);
     }
 
@@ -112,11 +238,11 @@ impl Lox {
         Self::set_had_error(true);
     }
 
-    fn had_error() -> bool {
+    pub(crate) fn had_error() -> bool {
         unsafe { *HAD_ERROR.lock().unwrap() }
     }
 
-    fn set_had_error(val: bool) {
+    pub(crate) fn set_had_error(val: bool) {
         unsafe {
             *HAD_ERROR.lock().unwrap() = val;
         }
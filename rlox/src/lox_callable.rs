@@ -1,11 +1,86 @@
-use crate::{errors::RuntimeError, interpreter::Interpreter, value::Value};
+use std::fmt::Display;
+
+use crate::{errors::RuntimeError, interpreter::Interpreter, token::Token, value::Value};
+
+/// Arguments given by parameter name, as in `f(1, b: 2)`.
+pub type KeywordArguments = Vec<(Token, Value)>;
+
+/// The numbers of positional arguments a callable accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    /// Parameters every call must give a value for.
+    pub required: usize,
+    /// Parameters after those, which have default values.
+    pub optional: usize,
+    /// Whether any further arguments are collected into a rest parameter.
+    pub variadic: bool,
+}
+
+impl Arity {
+    pub fn fixed(count: usize) -> Self {
+        Self {
+            required: count,
+            optional: 0,
+            variadic: false,
+        }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.required && (self.variadic || count <= self.required + self.optional)
+    }
+}
+
+/// Describes the accepted counts as in "Expected {}": `1 argument`, `2 arguments`,
+/// `1 to 3 arguments` or `at least 1 argument`.
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.variadic {
+            write!(f, "at least {}", self.required)?;
+        } else if self.optional > 0 {
+            write!(f, "{} to {}", self.required, self.required + self.optional)?;
+        } else {
+            write!(f, "{}", self.required)?;
+        }
+        if self.required == 1 && (self.variadic || self.optional == 0) {
+            write!(f, " argument")
+        } else {
+            write!(f, " arguments")
+        }
+    }
+}
 
 pub trait LoxCallable {
-    fn arity(&self) -> usize;
+    fn name(&self) -> String;
+    fn arity(&self) -> Arity;
+    /// Names of the parameters keyword arguments can be given for, in order. Natives
+    /// don't take keyword arguments.
+    fn parameters(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// How the callable is declared, as in `f(a, b = 2, ...rest)`, to describe it in
+    /// errors. Natives have no declaration.
+    fn signature(&self) -> Option<String> {
+        None
+    }
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: &[Value],
     ) -> Result<Value, RuntimeError>;
+    /// Calls with keyword arguments as well, which the interpreter has already matched
+    /// against `parameters`.
+    fn call_with_keywords(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+        keywords: &[(Token, Value)],
+    ) -> Result<Value, RuntimeError> {
+        debug_assert!(
+            keywords.is_empty(),
+            "{} has no named parameters",
+            self.name()
+        );
+        self.call(interpreter, arguments)
+    }
     fn string_repr(&self) -> String;
 }
@@ -1,27 +1,47 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
 use crate::{
-    errors::RuntimeError, interpreter::Interpreter, lox_callable::LoxCallable,
-    lox_function::LoxFunction, lox_instance::LoxInstance, value::Value,
+    errors::RuntimeError,
+    gc::Trace,
+    interpreter::Interpreter,
+    lox_callable::{Arity, LoxCallable},
+    lox_function::LoxFunction,
+    lox_instance::LoxInstance,
+    lox_trait::LoxTrait,
+    token::Token,
+    value::Value,
 };
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub struct LoxClass {
     name: String,
     superclass: Option<Box<LoxClass>>,
+    /// Traits the class mixes in. Their methods are already copied into `methods`.
+    traits: Vec<LoxTrait>,
     methods: HashMap<String, LoxFunction>,
+    /// Shared by every copy of the class, so two classes are only equal if they come from
+    /// the same evaluation of a class statement.
+    identity: Rc<()>,
 }
 
 impl LoxClass {
     pub fn new(
         name: &str,
         superclass: Option<Box<LoxClass>>,
+        traits: Vec<LoxTrait>,
         methods: HashMap<String, LoxFunction>,
     ) -> Self {
         Self {
             name: name.into(),
             superclass,
+            traits,
             methods,
+            identity: Rc::new(()),
         }
     }
 
@@ -31,6 +51,45 @@ impl LoxClass {
             .as_ref()
             .and_then(|class| class.find_method(name)))
     }
+
+    pub fn superclass(&self) -> Option<&LoxClass> {
+        self.superclass.as_deref()
+    }
+
+    /// Methods declared in the class or mixed in from its traits, without inherited ones.
+    pub fn methods(&self) -> &HashMap<String, LoxFunction> {
+        &self.methods
+    }
+
+    /// Whether this class or one it inherits from mixes in `used`.
+    pub fn uses_trait(&self, used: &LoxTrait) -> bool {
+        self.traits.contains(used)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|class| class.uses_trait(used))
+    }
+
+    /// Whether this is `other` or inherits from it.
+    pub fn is_subclass_of(&self, other: &LoxClass) -> bool {
+        self == other
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|class| class.is_subclass_of(other))
+    }
+}
+
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.identity, &other.identity)
+    }
+}
+
+impl Hash for LoxClass {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.identity).hash(state);
+    }
 }
 
 impl Display for LoxClass {
@@ -40,26 +99,55 @@ impl Display for LoxClass {
 }
 
 impl LoxCallable for LoxClass {
-    fn arity(&self) -> usize {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn arity(&self) -> Arity {
         if let Some(initializer) = self.find_method("init") {
             initializer.arity()
         } else {
-            0
+            Arity::fixed(0)
         }
     }
 
+    fn parameters(&self) -> Vec<String> {
+        self.find_method("init")
+            .map(|initializer| initializer.parameters())
+            .unwrap_or_default()
+    }
+
+    fn signature(&self) -> Option<String> {
+        let params = self
+            .find_method("init")
+            .map(LoxFunction::parameter_list)
+            .unwrap_or_default();
+        Some(format!("{}({})", self.name, params))
+    }
+
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        self.call_with_keywords(interpreter, arguments, &[])
+    }
+
+    fn call_with_keywords(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+        keywords: &[(Token, Value)],
     ) -> Result<Value, RuntimeError> {
         let instance: Value = LoxInstance::new(self).into();
         let initializer = self.find_method("init");
         match (&initializer, &instance) {
             (Some(initializer), Value::LoxInstance(instance)) => {
-                initializer
-                    .bind(instance.clone())
-                    .call(interpreter, arguments)?;
+                initializer.bind(instance.clone()).call_with_keywords(
+                    interpreter,
+                    arguments,
+                    keywords,
+                )?;
             }
             _ => {}
         }
@@ -67,6 +155,20 @@ impl LoxCallable for LoxClass {
     }
 
     fn string_repr(&self) -> String {
-        todo!()
+        self.name.clone()
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        for method in self.methods.values() {
+            method.trace(refs);
+        }
+        if let Some(superclass) = &self.superclass {
+            superclass.trace(refs);
+        }
+        for used in &self.traits {
+            used.trace(refs);
+        }
     }
 }
@@ -1,88 +1,137 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
 use crate::{
-    environment::Environment, errors::RuntimeError, interpreter::Interpreter,
-    lox_callable::LoxCallable, lox_instance::LoxInstance, stmt::Stmt, token::Token, value::Value,
+    ast_printer::AstPrinter,
+    environment::Environment,
+    errors::{RuntimeError, TailCall},
+    gc::{self, Trace},
+    interpreter::Interpreter,
+    lox_callable::{Arity, LoxCallable},
+    lox_generator::LoxGenerator,
+    lox_instance::LoxInstance,
+    stmt::FunctionDecl,
+    token::Token,
+    value::Value,
 };
 
 #[derive(Clone)]
 pub struct LoxFunction {
-    name: Token,
-    params: Vec<Token>,
-    body: Vec<Option<Stmt>>,
+    declaration: Rc<FunctionDecl>,
     closure: Rc<RefCell<Environment>>,
     is_initializer: bool,
 }
 
 impl LoxFunction {
     pub fn new(
-        name: &Token,
-        params: &[Token],
-        body: &[Option<Stmt>],
+        declaration: Rc<FunctionDecl>,
         closure: Rc<RefCell<Environment>>,
         is_initializer: bool,
     ) -> Self {
         LoxFunction {
-            name: name.clone(),
-            params: Vec::from(params),
-            body: Vec::from(body),
+            declaration,
             closure,
             is_initializer,
         }
     }
 
+    /// Line the function was declared on.
+    pub fn line(&self) -> usize {
+        self.declaration.name.line
+    }
+
+    /// The instance a method is bound to, which is the only slot of the scope `bind`
+    /// creates.
+    fn this(&self) -> Value {
+        self.closure.borrow().get_at(0, 0)
+    }
+
+    /// The instance a bound method belongs to. Only the scope `bind` creates defines
+    /// `this` as a local.
+    fn receiver(&self) -> Option<Rc<RefCell<LoxInstance>>> {
+        match self.closure.borrow().get_local("this") {
+            Some(Value::LoxInstance(instance)) => Some(instance),
+            _ => None,
+        }
+    }
+
     pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> Self {
         let mut environment = Environment::with_enclosing(self.closure.clone());
         environment.define("this".into(), instance.into());
         Self::new(
-            &self.name,
-            &self.params,
-            &self.body,
-            Rc::new(RefCell::new(environment)),
+            self.declaration.clone(),
+            gc::alloc_environment(environment),
             self.is_initializer,
         )
     }
-}
 
-impl LoxCallable for LoxFunction {
-    fn arity(&self) -> usize {
-        self.params.len()
+    /// Defines the parameters in a new scope for a call. Parameters the call left out get
+    /// their defaults, evaluated in that scope as far as it is defined.
+    fn bind_arguments(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+        keywords: &[(Token, Value)],
+    ) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+        let environment = gc::alloc_environment(Environment::with_enclosing(self.closure.clone()));
+        for (i, param) in self.declaration.params.iter().enumerate() {
+            let keyword = keywords
+                .iter()
+                .find(|(name, _)| name.lexeme == param.name.lexeme);
+            let value = match (arguments.get(i), keyword, &param.default) {
+                (Some(value), _, _) | (None, Some((_, value)), _) => value.clone(),
+                (None, None, Some(default)) => {
+                    let previous = interpreter.set_environment(environment.clone());
+                    let value = interpreter.evaluate(default);
+                    interpreter.set_environment(previous);
+                    value?
+                }
+                (None, None, None) => unreachable!("arguments were checked by the interpreter"),
+            };
+            environment
+                .borrow_mut()
+                .define(param.name.lexeme.clone(), value);
+        }
+        if let Some(rest) = &self.declaration.rest {
+            let extra = arguments
+                .get(self.declaration.params.len()..)
+                .unwrap_or(&[]);
+            environment
+                .borrow_mut()
+                .define(rest.lexeme.clone(), Value::from(extra.to_vec()));
+        }
+        Ok(environment)
     }
 
-    fn call(
+    /// Runs the body once. A tail call in it comes back as an error for `call` to make.
+    fn call_once(
         &self,
         interpreter: &mut Interpreter,
         arguments: &[Value],
+        keywords: &[(Token, Value)],
     ) -> Result<Value, RuntimeError> {
-        let mut environment = Environment::with_enclosing(self.closure.clone());
-        for (i, param) in self.params.iter().enumerate() {
-            environment.define(param.lexeme.clone(), arguments[i].clone())
+        let environment = self.bind_arguments(interpreter, arguments, keywords)?;
+        if self.declaration.is_generator {
+            let generator = LoxGenerator::new(self.declaration.clone(), environment);
+            return Ok(Value::Generator(gc::alloc_generator(generator)));
         }
-        let environment = Rc::new(RefCell::new(environment));
-        match interpreter.execute_block(&self.body, environment) {
+        match interpreter.execute_block(&self.declaration.body, environment) {
             Ok(_) => {
                 if self.is_initializer {
-                    Ok(self
-                        .closure
-                        .borrow()
-                        .get_at(0, "this")
-                        .expect("Could not find `this` bound to initializer scope"))
+                    Ok(self.this())
                 } else {
                     Ok(Value::Nil)
                 }
             }
             Err(RuntimeError {
-                token: _,
-                msg: _,
                 return_value: Some(v),
+                ..
             }) => {
                 if self.is_initializer {
-                    let value = self
-                        .closure
-                        .borrow()
-                        .get_at(0, "this")
-                        .expect("no keyword `this` found in initializer scope");
-                    Ok(value)
+                    Ok(self.this())
                 } else {
                     Ok(v)
                 }
@@ -91,16 +140,125 @@ impl LoxCallable for LoxFunction {
         }
     }
 
+    /// The parameters as declared, as in `a, b = 2, ...rest`. Default values are shown as
+    /// the syntax tree printer prints them.
+    pub fn parameter_list(&self) -> String {
+        let mut printer = AstPrinter::new();
+        let mut params: Vec<String> = self
+            .declaration
+            .params
+            .iter()
+            .map(|param| match &param.default {
+                Some(default) => {
+                    format!(
+                        "{} = {}",
+                        param.name.lexeme,
+                        default.accept_visitor(&mut printer)
+                    )
+                }
+                None => param.name.lexeme.clone(),
+            })
+            .collect();
+        if let Some(rest) = &self.declaration.rest {
+            params.push(format!("...{}", rest.lexeme));
+        }
+        params.join(", ")
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn name(&self) -> String {
+        self.declaration.name.lexeme.clone()
+    }
+
+    fn arity(&self) -> Arity {
+        let params = &self.declaration.params;
+        let required = params
+            .iter()
+            .take_while(|param| param.default.is_none())
+            .count();
+        Arity {
+            required,
+            optional: params.len() - required,
+            variadic: self.declaration.rest.is_some(),
+        }
+    }
+
+    fn parameters(&self) -> Vec<String> {
+        self.declaration
+            .params
+            .iter()
+            .map(|param| param.name.lexeme.clone())
+            .collect()
+    }
+
+    fn signature(&self) -> Option<String> {
+        Some(format!("{}({})", self.name(), self.parameter_list()))
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        self.call_with_keywords(interpreter, arguments, &[])
+    }
+
+    fn call_with_keywords(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+        keywords: &[(Token, Value)],
+    ) -> Result<Value, RuntimeError> {
+        let mut res = self.call_once(interpreter, arguments, keywords);
+        // tail calls are made here rather than nested inside the caller's body, so
+        // tail-recursive code runs in constant native stack
+        while let Err(RuntimeError {
+            tail_call: Some(tail_call),
+            ..
+        }) = res
+        {
+            let TailCall {
+                function,
+                arguments,
+                keywords,
+            } = *tail_call;
+            res = function.call_once(interpreter, &arguments, &keywords);
+        }
+        res
+    }
+
     fn string_repr(&self) -> String {
-        format!("<fn {}>", self.name.lexeme)
+        format!("<fn {}>", self.declaration.name.lexeme)
     }
 }
 
+/// Two functions are equal if they were declared by the same statement and close over the
+/// same scope. Binding a method creates a new scope each time, so bound methods are equal
+/// if they are bound to the same instance.
 impl PartialEq for LoxFunction {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
-            && self.params == other.params
-            && self.body == other.body
-            && self.closure.as_ptr() == other.closure.as_ptr()
+        if !Rc::ptr_eq(&self.declaration, &other.declaration) {
+            return false;
+        }
+        if Rc::ptr_eq(&self.closure, &other.closure) {
+            return true;
+        }
+        match (self.receiver(), other.receiver()) {
+            (Some(a), Some(b)) => Rc::ptr_eq(&a, &b),
+            _ => false,
+        }
+    }
+}
+
+impl Hash for LoxFunction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.declaration).hash(state);
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        refs.push(Rc::as_ptr(&self.closure) as *const ());
     }
 }
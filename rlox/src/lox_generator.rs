@@ -0,0 +1,464 @@
+use std::{cell::RefCell, fmt::Display, mem, rc::Rc};
+
+use crate::{
+    environment::Environment,
+    errors::RuntimeError,
+    expr::Expr,
+    gc::{self, Trace},
+    interpreter::{Interpreter, Iteration},
+    lox_callable::{Arity, LoxCallable},
+    native_functions,
+    stmt::{FunctionDecl, Stmt},
+    token::Token,
+    value::Value,
+};
+
+/// The suspended call of a function whose body yields.
+///
+/// Calling such a function runs none of its body. Each `next()` or `send(value)` runs it
+/// up to its next `yield` and returns the value yielded, and `send` makes `value` the
+/// result of the `yield` the body continues from. Once the body has finished, `done` is
+/// true, `result` is the value it returned and `next()` returns nil. A runtime error in
+/// the body finishes the generator too, after reaching whoever resumed it.
+///
+/// The interpreter evaluates on the Rust stack, so a body can't just be paused halfway.
+/// Instead its blocks, branches and loops are run by an `Executor`, which records where
+/// each of them was as a `yield` unwinds them and walks back down those frames to resume.
+/// Expressions are still evaluated by the interpreter, so a `yield` has to be the first
+/// thing its statement evaluates.
+pub struct LoxGenerator {
+    declaration: Rc<FunctionDecl>,
+    state: State,
+    /// What the body returned, once it has.
+    result: Value,
+}
+
+enum State {
+    /// Created by a call, with the scope holding its arguments.
+    Ready(Rc<RefCell<Environment>>),
+    /// Stopped at a `yield`, with the frames of the statements around it, innermost first.
+    Suspended(Vec<Frame>),
+    Running,
+    Done,
+}
+
+/// Where a statement was when a `yield` inside it suspended the generator.
+enum Frame {
+    Block {
+        index: usize,
+        environment: Rc<RefCell<Environment>>,
+    },
+    If {
+        then: bool,
+    },
+    While,
+    ForIn {
+        iteration: Iteration,
+        environment: Rc<RefCell<Environment>>,
+    },
+    Match {
+        arm: usize,
+        environment: Rc<RefCell<Environment>>,
+    },
+}
+
+/// How a statement run by the `Executor` ended.
+enum Flow {
+    Completed,
+    Yielded(Value),
+}
+
+/// Runs a generator body from its start, or from the frames it was suspended with.
+struct Executor {
+    /// Frames still to resume into, innermost first.
+    frames: Vec<Frame>,
+    /// Value the generator is resumed with, until the `yield` it stopped at takes it.
+    sent: Option<Value>,
+}
+
+impl Executor {
+    /// The frame of the statement being entered, if the generator is resuming into it.
+    fn resume(&mut self) -> Option<Frame> {
+        if self.sent.is_some() {
+            self.frames.pop()
+        } else {
+            None
+        }
+    }
+
+    fn execute_block(
+        &mut self,
+        interpreter: &mut Interpreter,
+        statements: &[Option<Stmt>],
+        environment: impl FnOnce() -> Rc<RefCell<Environment>>,
+    ) -> Result<Flow, RuntimeError> {
+        let (start, environment) = match self.resume() {
+            Some(Frame::Block { index, environment }) => (index, environment),
+            Some(_) => unreachable!("resumed with the frame of another statement"),
+            None => (0, environment()),
+        };
+        let previous = interpreter.set_environment(environment.clone());
+        let mut res = Ok(Flow::Completed);
+        for (index, statement) in statements.iter().enumerate().skip(start) {
+            res = self.execute(interpreter, statement.as_ref().unwrap());
+            match res {
+                Ok(Flow::Completed) => {}
+                Ok(Flow::Yielded(_)) => {
+                    self.frames.push(Frame::Block { index, environment });
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        interpreter.set_environment(previous);
+        res
+    }
+
+    fn execute(
+        &mut self,
+        interpreter: &mut Interpreter,
+        statement: &Stmt,
+    ) -> Result<Flow, RuntimeError> {
+        if let Some(Expr::Yield { value, .. }) = statement.first_yield() {
+            return self.execute_yield(interpreter, statement, value.as_deref());
+        }
+        match statement {
+            Stmt::Block { statements } => {
+                if self.sent.is_none() {
+                    interpreter.enter_statement(statement);
+                }
+                let enclosing = interpreter.environment();
+                self.execute_block(interpreter, statements, || {
+                    gc::alloc_environment(Environment::with_enclosing(enclosing))
+                })
+            }
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let then = match self.resume() {
+                    Some(Frame::If { then }) => then,
+                    Some(_) => unreachable!("resumed with the frame of another statement"),
+                    None => {
+                        interpreter.enter_statement(statement);
+                        let condition = interpreter.evaluate(condition)?;
+                        let then = interpreter.is_truthy(&condition);
+                        interpreter.branch(keyword, if then { 0 } else { 1 });
+                        then
+                    }
+                };
+                let branch = if then {
+                    Some(then_branch.as_ref())
+                } else {
+                    else_branch.as_ref().as_ref()
+                };
+                let Some(branch) = branch else {
+                    return Ok(Flow::Completed);
+                };
+                let flow = self.execute(interpreter, branch)?;
+                if let Flow::Yielded(_) = flow {
+                    self.frames.push(Frame::If { then });
+                }
+                Ok(flow)
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+            } => {
+                let mut resuming = match self.resume() {
+                    Some(Frame::While) => true,
+                    Some(_) => unreachable!("resumed with the frame of another statement"),
+                    None => {
+                        interpreter.enter_statement(statement);
+                        false
+                    }
+                };
+                loop {
+                    if !resuming {
+                        let condition = interpreter.evaluate(condition)?;
+                        if !interpreter.is_truthy(&condition) {
+                            interpreter.branch(keyword, 1);
+                            return Ok(Flow::Completed);
+                        }
+                        interpreter.branch(keyword, 0);
+                    }
+                    resuming = false;
+                    if let Flow::Yielded(value) = self.execute(interpreter, body)? {
+                        self.frames.push(Frame::While);
+                        return Ok(Flow::Yielded(value));
+                    }
+                }
+            }
+            Stmt::ForIn {
+                keyword,
+                name,
+                iterable,
+                body,
+            } => {
+                let (mut iteration, mut current) = match self.resume() {
+                    Some(Frame::ForIn {
+                        iteration,
+                        environment,
+                    }) => (iteration, Some(environment)),
+                    Some(_) => unreachable!("resumed with the frame of another statement"),
+                    None => {
+                        interpreter.enter_statement(statement);
+                        let iterable = interpreter.evaluate(iterable)?;
+                        (interpreter.iterate(keyword, iterable)?, None)
+                    }
+                };
+                loop {
+                    let environment = match current.take() {
+                        Some(environment) => environment,
+                        None => match interpreter.next_element(keyword, &mut iteration)? {
+                            Some(element) => {
+                                interpreter.branch(keyword, 0);
+                                let mut environment =
+                                    Environment::with_enclosing(interpreter.environment());
+                                environment.define(name.lexeme.clone(), element);
+                                gc::alloc_environment(environment)
+                            }
+                            None => {
+                                interpreter.branch(keyword, 1);
+                                return Ok(Flow::Completed);
+                            }
+                        },
+                    };
+                    let previous = interpreter.set_environment(environment.clone());
+                    let flow = self.execute(interpreter, body);
+                    interpreter.set_environment(previous);
+                    if let Flow::Yielded(value) = flow? {
+                        self.frames.push(Frame::ForIn {
+                            iteration,
+                            environment,
+                        });
+                        return Ok(Flow::Yielded(value));
+                    }
+                }
+            }
+            Stmt::Match {
+                keyword,
+                subject,
+                arms,
+            } => {
+                let (arm, environment) = match self.resume() {
+                    Some(Frame::Match { arm, environment }) => (arm, environment),
+                    Some(_) => unreachable!("resumed with the frame of another statement"),
+                    None => {
+                        interpreter.enter_statement(statement);
+                        let subject = interpreter.evaluate(subject)?;
+                        interpreter.select_arm(keyword, &subject, arms)?
+                    }
+                };
+                let previous = interpreter.set_environment(environment.clone());
+                let flow = self.execute(interpreter, &arms[arm].body);
+                interpreter.set_environment(previous);
+                if let Flow::Yielded(value) = flow? {
+                    self.frames.push(Frame::Match { arm, environment });
+                    return Ok(Flow::Yielded(value));
+                }
+                Ok(Flow::Completed)
+            }
+            // statements without a `yield` of their own run as usual; any `yield` in a
+            // function or class they declare belongs to that function
+            _ => {
+                interpreter.execute(statement)?;
+                Ok(Flow::Completed)
+            }
+        }
+    }
+
+    /// Suspends at a statement that starts with a `yield`, or, if it is the one the
+    /// generator is resuming at, runs the rest of it with the value sent in.
+    fn execute_yield(
+        &mut self,
+        interpreter: &mut Interpreter,
+        statement: &Stmt,
+        value: Option<&Expr>,
+    ) -> Result<Flow, RuntimeError> {
+        if let Some(sent) = self.sent.take() {
+            interpreter.resume_yield(sent);
+            interpreter.execute_resumed(statement)?;
+            return Ok(Flow::Completed);
+        }
+        interpreter.enter_statement(statement);
+        let value = match value {
+            Some(value) => interpreter.evaluate(value)?,
+            None => Value::Nil,
+        };
+        Ok(Flow::Yielded(value))
+    }
+}
+
+impl LoxGenerator {
+    pub fn new(declaration: Rc<FunctionDecl>, environment: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            declaration,
+            state: State::Ready(environment),
+            result: Value::Nil,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Finishes the generator and drops the scopes it was suspended in, breaking any cycle
+    /// through them.
+    pub fn clear(&mut self) {
+        self.state = State::Done;
+        self.result = Value::Nil;
+    }
+
+    /// Runs the body of `generator` until it yields or finishes, with `sent` as the value
+    /// of the `yield` it continues from. Returns the value yielded, or nil if the body
+    /// finished.
+    pub fn resume(
+        generator: &Rc<RefCell<Self>>,
+        interpreter: &mut Interpreter,
+        sent: Value,
+    ) -> Result<Value, RuntimeError> {
+        let declaration = generator.borrow().declaration.clone();
+        let state = mem::replace(&mut generator.borrow_mut().state, State::Running);
+        let mut executor = Executor {
+            frames: Vec::new(),
+            sent: None,
+        };
+        let res = match state {
+            State::Ready(environment) => {
+                if sent != Value::Nil {
+                    generator.borrow_mut().state = State::Ready(environment);
+                    return Err(native_functions::error(
+                        "Can't send a value to a generator that hasn't started.",
+                    ));
+                }
+                executor.execute_block(interpreter, &declaration.body, || environment)
+            }
+            State::Suspended(frames) => {
+                executor.frames = frames;
+                executor.sent = Some(sent);
+                executor.execute_block(interpreter, &declaration.body, || {
+                    unreachable!("a suspended generator resumes into its body")
+                })
+            }
+            State::Running => {
+                return Err(native_functions::error("Generator is already running."));
+            }
+            State::Done => {
+                generator.borrow_mut().state = State::Done;
+                return Ok(Value::Nil);
+            }
+        };
+
+        let mut generator = generator.borrow_mut();
+        match res {
+            Ok(Flow::Yielded(value)) => {
+                generator.state = State::Suspended(executor.frames);
+                Ok(value)
+            }
+            Ok(Flow::Completed) => {
+                generator.state = State::Done;
+                Ok(Value::Nil)
+            }
+            Err(RuntimeError {
+                return_value: Some(value),
+                ..
+            }) => {
+                generator.state = State::Done;
+                generator.result = value;
+                Ok(Value::Nil)
+            }
+            Err(e) => {
+                generator.state = State::Done;
+                Err(e)
+            }
+        }
+    }
+
+    /// Looks up a property of `generator`: its `next` and `send` methods, or its `done`
+    /// and `result` fields.
+    pub fn get(generator: &Rc<RefCell<Self>>, name: &Token) -> Result<Value, RuntimeError> {
+        let method = |send| {
+            Value::NativeMethod(Rc::new(GeneratorMethod {
+                generator: generator.clone(),
+                send,
+            }))
+        };
+        match name.lexeme.as_str() {
+            "next" => Ok(method(false)),
+            "send" => Ok(method(true)),
+            "done" => Ok(Value::Bool(generator.borrow().is_done())),
+            "result" => Ok(generator.borrow().result.clone()),
+            _ => Err(RuntimeError::new(
+                name.clone(),
+                format!("Undefined property '{}'", name.lexeme),
+            )),
+        }
+    }
+}
+
+impl Trace for LoxGenerator {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        match &self.state {
+            State::Ready(environment) => refs.push(Rc::as_ptr(environment) as *const ()),
+            State::Suspended(frames) => {
+                for frame in frames {
+                    match frame {
+                        Frame::Block { environment, .. } | Frame::Match { environment, .. } => {
+                            refs.push(Rc::as_ptr(environment) as *const ())
+                        }
+                        Frame::ForIn {
+                            iteration,
+                            environment,
+                        } => {
+                            iteration.trace(refs);
+                            refs.push(Rc::as_ptr(environment) as *const ());
+                        }
+                        Frame::If { .. } | Frame::While => {}
+                    }
+                }
+            }
+            // while running, the frames are on the Rust stack and count as roots
+            State::Running | State::Done => {}
+        }
+        self.result.trace(refs);
+    }
+}
+
+impl Display for LoxGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<generator {}>", self.declaration.name.lexeme)
+    }
+}
+
+/// `next()` or `send(value)`, bound to the generator it was looked up on.
+struct GeneratorMethod {
+    generator: Rc<RefCell<LoxGenerator>>,
+    send: bool,
+}
+
+impl LoxCallable for GeneratorMethod {
+    fn name(&self) -> String {
+        if self.send { "send" } else { "next" }.into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(if self.send { 1 } else { 0 })
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let sent = arguments.first().cloned().unwrap_or(Value::Nil);
+        LoxGenerator::resume(&self.generator, interpreter, sent)
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
@@ -1,6 +1,6 @@
 use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
 
-use crate::{errors::RuntimeError, lox_class::LoxClass, token::Token, value::Value};
+use crate::{errors::RuntimeError, gc::Trace, lox_class::LoxClass, token::Token, value::Value};
 
 #[derive(Clone, PartialEq)]
 pub struct LoxInstance {
@@ -16,6 +16,19 @@ impl LoxInstance {
         }
     }
 
+    pub fn class(&self) -> &LoxClass {
+        &self.class
+    }
+
+    pub fn fields(&self) -> &HashMap<String, Value> {
+        &self.fields
+    }
+
+    /// The field called `name`, without looking at methods.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+
     pub fn get(instance: Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Value, RuntimeError> {
         if let Some(v) = instance.borrow().fields.get(&name.lexeme) {
             return Ok(v.clone());
@@ -34,6 +47,11 @@ impl LoxInstance {
     pub fn set(&mut self, name: &Token, value: &Value) {
         self.fields.insert((&name.lexeme).into(), value.clone());
     }
+
+    /// Drops every field.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
 }
 
 impl Display for LoxInstance {
@@ -41,3 +59,12 @@ impl Display for LoxInstance {
         write!(f, "{} instance", self.class)
     }
 }
+
+impl Trace for LoxInstance {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        self.class.trace(refs);
+        for value in self.fields.values() {
+            value.trace(refs);
+        }
+    }
+}
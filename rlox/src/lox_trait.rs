@@ -0,0 +1,97 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    environment::Environment,
+    gc::{self, Trace},
+    lox_class::LoxClass,
+    lox_function::LoxFunction,
+    stmt::FunctionDecl,
+    value::Value,
+};
+
+/// Methods declared with `trait Name { ... }`, which classes mix in with `with Name`.
+///
+/// A trait doesn't know the classes it is used by, so its methods only become functions
+/// once a class mixes them in. Inside them `super` is the superclass of that class.
+#[derive(Clone)]
+pub struct LoxTrait {
+    name: String,
+    methods: HashMap<String, Rc<FunctionDecl>>,
+    /// Scope the trait was declared in, which its methods close over.
+    closure: Rc<RefCell<Environment>>,
+    /// Shared by every copy of the trait, so two traits are only equal if they come from
+    /// the same evaluation of a trait statement.
+    identity: Rc<()>,
+}
+
+impl LoxTrait {
+    pub fn new(
+        name: &str,
+        methods: HashMap<String, Rc<FunctionDecl>>,
+        closure: Rc<RefCell<Environment>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            methods,
+            closure,
+            identity: Rc::new(()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Names of the trait's methods, sorted.
+    pub fn method_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.methods.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// The trait's methods as mixed into a class with the given superclass, which is what
+    /// `super` refers to in them. Without one, using `super` is a runtime error.
+    pub fn methods_for(&self, superclass: Option<&LoxClass>) -> HashMap<String, LoxFunction> {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        let superclass = superclass.map_or(Value::Nil, |class| class.clone().into());
+        environment.define("super".into(), superclass);
+        let environment = gc::alloc_environment(environment);
+        self.methods
+            .iter()
+            .map(|(name, declaration)| {
+                let function = LoxFunction::new(declaration.clone(), environment.clone(), false);
+                (name.clone(), function)
+            })
+            .collect()
+    }
+}
+
+impl PartialEq for LoxTrait {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.identity, &other.identity)
+    }
+}
+
+impl Hash for LoxTrait {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.identity).hash(state);
+    }
+}
+
+impl Display for LoxTrait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+impl Trace for LoxTrait {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        refs.push(Rc::as_ptr(&self.closure) as *const ());
+    }
+}
@@ -1,18 +1,90 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use num_bigint_dig::BigInt;
+use num_traits::FromPrimitive;
 use ordered_float::OrderedFloat;
 
 use crate::{
-    errors::RuntimeError, interpreter::Interpreter, lox_callable::LoxCallable, value::Value,
+    errors::RuntimeError,
+    gc,
+    interpreter::Interpreter,
+    lox_callable::{Arity, LoxCallable},
+    lox_class::LoxClass,
+    lox_instance::LoxInstance,
+    token::{Token, TokenLiteral, TokenType},
+    value::Value,
 };
 
 pub const CLOCK_FN: Clock = Clock;
+pub const GC_FN: Gc = Gc;
+pub const GC_STATS_FN: GcStatsFn = GcStatsFn;
+pub const BIGINT_FN: BigIntFn = BigIntFn;
+pub const SPAWN_FN: Spawn = Spawn;
+pub const RUN_FN: Run = Run;
+pub const CLASS_OF_FN: ClassOf = ClassOf;
+pub const NAME_OF_FN: NameOf = NameOf;
+pub const SUPERCLASS_OF_FN: SuperclassOf = SuperclassOf;
+pub const FIELDS_FN: Fields = Fields;
+pub const METHODS_FN: Methods = Methods;
+pub const HAS_FIELD_FN: HasField = HasField;
+pub const GET_FIELD_FN: GetField = GetField;
+pub const SET_FIELD_FN: SetField = SetField;
+pub const IS_INSTANCE_FN: IsInstance = IsInstance;
+
+/// An error raised by a native function. The interpreter reports it at the call.
+pub fn error(msg: &str) -> RuntimeError {
+    RuntimeError::new(
+        Token::new(TokenType::EOF, "", &TokenLiteral::Nil, 0, 0),
+        msg.into(),
+    )
+}
+
+fn instance(value: &Value) -> Result<&Rc<RefCell<LoxInstance>>, RuntimeError> {
+    match value {
+        Value::LoxInstance(instance) => Ok(instance),
+        _ => Err(error("Argument must be an instance.")),
+    }
+}
+
+fn class(value: &Value) -> Result<&LoxClass, RuntimeError> {
+    match value {
+        Value::LoxClass(class) => Ok(class),
+        _ => Err(error("Argument must be a class.")),
+    }
+}
+
+fn field_name(value: &Value) -> Result<&str, RuntimeError> {
+    match value {
+        Value::String(name) => Ok(name),
+        _ => Err(error("Field name must be a string.")),
+    }
+}
+
+/// A tuple of `names`, sorted.
+fn names<'a>(names: impl IntoIterator<Item = &'a str>) -> Value {
+    let mut names: Vec<&str> = names.into_iter().collect();
+    names.sort();
+    names.dedup();
+    let names: Vec<Value> = names
+        .into_iter()
+        .map(|name| Value::String(name.into()))
+        .collect();
+    Value::from(names)
+}
 
 pub struct Clock;
 
 impl LoxCallable for Clock {
-    fn arity(&self) -> usize {
-        0
+    fn name(&self) -> String {
+        "clock".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(0)
     }
 
     fn call(
@@ -31,3 +103,430 @@ impl LoxCallable for Clock {
         "<native fn>".into()
     }
 }
+
+/// Runs the cycle collector and returns the number of objects it reclaimed.
+pub struct Gc;
+
+impl LoxCallable for Gc {
+    fn name(&self) -> String {
+        "gc".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(0)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let before = gc::stats().reclaimed;
+        let reclaimed = gc::collect().reclaimed - before;
+        Ok(Value::Integer(reclaimed as i64))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the live `(environments, instances, generators, tuples)` tracked by the cycle
+/// collector.
+pub struct GcStatsFn;
+
+impl LoxCallable for GcStatsFn {
+    fn name(&self) -> String {
+        "gc_stats".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(0)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let stats = gc::stats();
+        let counts = [
+            stats.environments,
+            stats.instances,
+            stats.generators,
+            stats.tuples,
+        ];
+        Ok(Value::from(
+            counts
+                .into_iter()
+                .map(|count| Value::Integer(count as i64))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Converts an integer, a float without a fractional part or a string of digits to a big
+/// integer.
+pub struct BigIntFn;
+
+impl LoxCallable for BigIntFn {
+    fn name(&self) -> String {
+        "bigint".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let value = match &arguments[0] {
+            Value::BigInt(n) => return Ok(Value::BigInt(n.clone())),
+            Value::Integer(n) => Some(BigInt::from(*n)),
+            Value::Number(n) if n.fract() == 0.0 => BigInt::from_f64(**n),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        };
+        value
+            .map(Value::from)
+            .ok_or_else(|| error("Argument must be an integer or a string of digits."))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Queues a generator as a coroutine for `run` and returns it.
+pub struct Spawn;
+
+impl LoxCallable for Spawn {
+    fn name(&self) -> String {
+        "spawn".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        match &arguments[0] {
+            Value::Generator(generator) => {
+                interpreter.spawn(generator.clone());
+                Ok(arguments[0].clone())
+            }
+            _ => Err(error("Can only spawn generators.")),
+        }
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Runs the spawned coroutines round-robin, each up to its next `yield`, until all of them
+/// have finished.
+pub struct Run;
+
+impl LoxCallable for Run {
+    fn name(&self) -> String {
+        "run".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(0)
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        interpreter.run_tasks()?;
+        Ok(Value::Nil)
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the class of an instance.
+pub struct ClassOf;
+
+impl LoxCallable for ClassOf {
+    fn name(&self) -> String {
+        "class_of".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let instance = instance(&arguments[0])?;
+        let class = instance.borrow().class().clone();
+        Ok(class.into())
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the name of a class, trait or function.
+pub struct NameOf;
+
+impl LoxCallable for NameOf {
+    fn name(&self) -> String {
+        "name_of".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let name = match &arguments[0] {
+            Value::LoxClass(class) => class.name(),
+            Value::LoxTrait(used) => used.name().into(),
+            Value::LoxFn(function) => function.name(),
+            Value::NativeFn(function) => function.name(),
+            Value::NativeMethod(function) => function.name(),
+            _ => return Err(error("Argument must be a class, trait or function.")),
+        };
+        Ok(Value::from(name))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the class a class inherits from, or nil if it has none.
+pub struct SuperclassOf;
+
+impl LoxCallable for SuperclassOf {
+    fn name(&self) -> String {
+        "superclass_of".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let class = class(&arguments[0])?;
+        Ok(class
+            .superclass()
+            .map_or(Value::Nil, |superclass| superclass.clone().into()))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the names of an instance's fields as a sorted tuple.
+pub struct Fields;
+
+impl LoxCallable for Fields {
+    fn name(&self) -> String {
+        "fields".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let instance = instance(&arguments[0])?.borrow();
+        Ok(names(instance.fields().keys().map(String::as_str)))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the names of the methods instances of a class respond to, including inherited
+/// ones, or the methods of a trait, as a sorted tuple.
+pub struct Methods;
+
+impl LoxCallable for Methods {
+    fn name(&self) -> String {
+        "methods".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(1)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        if let Value::LoxTrait(used) = &arguments[0] {
+            return Ok(names(used.method_names()));
+        }
+        let mut class =
+            Some(class(&arguments[0]).map_err(|_| error("Argument must be a class or trait."))?);
+        let mut methods = Vec::new();
+        while let Some(current) = class {
+            methods.extend(current.methods().keys().map(String::as_str));
+            class = current.superclass();
+        }
+        Ok(names(methods))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns whether an instance has a field with the given name.
+pub struct HasField;
+
+impl LoxCallable for HasField {
+    fn name(&self) -> String {
+        "has_field".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(2)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let instance = instance(&arguments[0])?;
+        let name = field_name(&arguments[1])?;
+        Ok(Value::from(instance.borrow().field(name).is_some()))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns the value of an instance's field with the given name.
+pub struct GetField;
+
+impl LoxCallable for GetField {
+    fn name(&self) -> String {
+        "get_field".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(2)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let instance = instance(&arguments[0])?;
+        let name = field_name(&arguments[1])?;
+        let value = instance.borrow().field(name);
+        value.ok_or_else(|| error(&format!("Undefined field '{}'.", name)))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Sets the field of an instance with the given name and returns the value.
+pub struct SetField;
+
+impl LoxCallable for SetField {
+    fn name(&self) -> String {
+        "set_field".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(3)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let instance = instance(&arguments[0])?;
+        let name = field_name(&arguments[1])?;
+        let token = Token::new(TokenType::Identifier, name, &TokenLiteral::Nil, 0, 0);
+        instance.borrow_mut().set(&token, &arguments[2]);
+        Ok(arguments[2].clone())
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
+
+/// Returns whether a value is an instance of a class or one of its subclasses, or of a
+/// class using a trait.
+pub struct IsInstance;
+
+impl LoxCallable for IsInstance {
+    fn name(&self) -> String {
+        "is_instance".into()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::fixed(2)
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let object_class = match &arguments[0] {
+            Value::LoxInstance(instance) => Some(instance.borrow().class().clone()),
+            _ => None,
+        };
+        let is_instance = match &arguments[1] {
+            Value::LoxClass(class) => object_class.is_some_and(|c| c.is_subclass_of(class)),
+            Value::LoxTrait(used) => object_class.is_some_and(|c| c.uses_trait(used)),
+            _ => return Err(error("Argument must be a class or trait.")),
+        };
+        Ok(Value::from(is_instance))
+    }
+
+    fn string_repr(&self) -> String {
+        "<native fn>".into()
+    }
+}
@@ -0,0 +1,473 @@
+use std::rc::Rc;
+
+use crate::{
+    expr::{Expr, ExprId, ExprVisitor},
+    interpreter::Interpreter,
+    stmt::{FunctionDecl, MatchArm, Param, Stmt, StmtVisitor, TypeAnnotation},
+    token::{Token, TokenLiteral, TokenType},
+    value::Value,
+};
+
+/// Rewrites a resolved program into an equivalent one that does less work at runtime.
+///
+/// Operators whose operands are all literals are folded, branches and loops with a literal
+/// condition are reduced to the code that can actually run, and `!!x` is simplified to `x`
+/// where only its truthiness matters. An expression that would fail at runtime is left as
+/// it is, so the error is still reported on its own line.
+///
+/// Variable expressions keep their IDs, so the resolutions recorded for the original tree
+/// still apply.
+pub struct Optimizer {
+    /// Evaluates constant expressions, so folding follows exactly what the interpreter does.
+    evaluator: Interpreter,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self {
+            evaluator: Interpreter::new(),
+        }
+    }
+
+    pub fn optimize(&mut self, statements: &[Option<Stmt>]) -> Vec<Option<Stmt>> {
+        statements
+            .iter()
+            .flatten()
+            .filter_map(|statement| self.optimize_stmt(statement))
+            .map(Some)
+            .collect()
+    }
+
+    fn optimize_stmt(&mut self, statement: &Stmt) -> Option<Stmt> {
+        statement.accept_visitor(self)
+    }
+
+    /// Optimizes a statement that must stay in place, such as the body of a loop.
+    fn optimize_nested(&mut self, statement: &Stmt) -> Stmt {
+        self.optimize_stmt(statement)
+            .unwrap_or_else(|| Stmt::new_block(Vec::new()))
+    }
+
+    fn optimize_expr(&mut self, expr: &Expr) -> Expr {
+        expr.accept_visitor(self)
+    }
+
+    /// Optimizes an expression whose value is only tested for truthiness.
+    fn optimize_condition(&mut self, expr: &Expr) -> Expr {
+        let expr = self.optimize_expr(expr);
+        simplify_condition(expr)
+    }
+
+    /// Replaces `expr` with its value if it can be computed without error.
+    fn fold(&mut self, expr: Expr) -> Expr {
+        match self.evaluator.evaluate_unresolved(&expr) {
+            Ok(value) => match literal(value) {
+                Some(value) => match expr.token() {
+                    Some(token) => Expr::new_literal_at(token.clone(), value),
+                    None => Expr::new_literal(value),
+                },
+                None => expr,
+            },
+            Err(_) => expr,
+        }
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops pairs of `!` whose only effect is converting a value to a boolean.
+fn simplify_condition(expr: Expr) -> Expr {
+    match expr {
+        Expr::Unary { operator, right } if operator.ttype == TokenType::Bang => match *right {
+            Expr::Unary {
+                operator: inner,
+                right,
+            } if inner.ttype == TokenType::Bang => simplify_condition(*right),
+            right => Expr::new_unary(operator, right),
+        },
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => Expr::new_logical(
+            simplify_condition(*left),
+            operator,
+            simplify_condition(*right),
+        ),
+        Expr::Grouping { expression } => Expr::new_grouping(simplify_condition(*expression)),
+        expr => expr,
+    }
+}
+
+fn literal(value: Value) -> Option<TokenLiteral> {
+    match value {
+        Value::Nil => Some(TokenLiteral::Nil),
+        Value::String(s) => Some(TokenLiteral::String(s)),
+        Value::Number(n) => Some(TokenLiteral::Number(n)),
+        Value::Integer(n) => Some(TokenLiteral::Integer(n)),
+        Value::BigInt(n) => Some(TokenLiteral::BigInt(n)),
+        Value::Bool(b) => Some(TokenLiteral::Bool(b)),
+        _ => None,
+    }
+}
+
+fn is_truthy(literal: &TokenLiteral) -> bool {
+    !matches!(literal, TokenLiteral::Nil | TokenLiteral::Bool(false))
+}
+
+impl StmtVisitor for Optimizer {
+    type Output = Option<Stmt>;
+
+    fn visit_block(&mut self, statements: &Vec<Option<Stmt>>) -> Self::Output {
+        Some(Stmt::new_block(self.optimize(statements)))
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        traits: &[Expr],
+        methods: &Vec<Stmt>,
+    ) -> Self::Output {
+        let methods = methods
+            .iter()
+            .map(|method| self.optimize_nested(method))
+            .collect();
+        Some(Stmt::new_class(
+            name.clone(),
+            superclass.clone(),
+            traits.to_vec(),
+            methods,
+        ))
+    }
+
+    fn visit_const(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output {
+        let initializer = self.optimize_expr(initializer);
+        Some(Stmt::new_const(
+            name.clone(),
+            annotation.cloned(),
+            initializer,
+        ))
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) -> Self::Output {
+        Some(Stmt::new_expression(self.optimize_expr(expression)))
+    }
+
+    fn visit_for_in(
+        &mut self,
+        keyword: &Token,
+        name: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        let iterable = self.optimize_expr(iterable);
+        let body = self.optimize_nested(body);
+        Some(Stmt::new_for_in(
+            keyword.clone(),
+            name.clone(),
+            iterable,
+            body,
+        ))
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output {
+        let params = declaration
+            .params
+            .iter()
+            .map(|param| Param {
+                name: param.name.clone(),
+                annotation: param.annotation.clone(),
+                default: param
+                    .default
+                    .as_ref()
+                    .map(|default| self.optimize_expr(default)),
+            })
+            .collect();
+        Some(Stmt::new_function(
+            declaration.name.clone(),
+            params,
+            declaration.rest.clone(),
+            declaration.return_type.clone(),
+            self.optimize(&declaration.body),
+            declaration.is_generator,
+        ))
+    }
+
+    fn visit_if(
+        &mut self,
+        keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Self::Output {
+        let condition = self.optimize_condition(condition);
+        if let Expr::Literal { value, .. } = &condition {
+            return if is_truthy(value) {
+                self.optimize_stmt(then_branch)
+            } else {
+                else_branch
+                    .as_ref()
+                    .and_then(|else_branch| self.optimize_stmt(else_branch))
+            };
+        }
+        let then_branch = self.optimize_nested(then_branch);
+        let else_branch = else_branch
+            .as_ref()
+            .and_then(|else_branch| self.optimize_stmt(else_branch));
+        Some(Stmt::new_if(
+            keyword.clone(),
+            condition,
+            then_branch,
+            else_branch,
+        ))
+    }
+
+    fn visit_match(&mut self, keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output {
+        let subject = self.optimize_expr(subject);
+        let arms = arms
+            .iter()
+            .map(|arm| MatchArm {
+                pattern: arm.pattern.clone(),
+                guard: arm
+                    .guard
+                    .as_ref()
+                    .map(|guard| self.optimize_condition(guard)),
+                body: self.optimize_nested(&arm.body),
+            })
+            .collect();
+        Some(Stmt::new_match(keyword.clone(), subject, arms))
+    }
+
+    fn visit_print(&mut self, keyword: &Token, expression: &Expr) -> Self::Output {
+        Some(Stmt::new_print(
+            keyword.clone(),
+            self.optimize_expr(expression),
+        ))
+    }
+
+    fn visit_return(&mut self, keyword: &Token, value: &Option<Rc<Expr>>) -> Self::Output {
+        Some(match value {
+            Some(value) => Stmt::new_return(keyword.clone(), self.optimize_expr(value)),
+            None => Stmt::new_return_void(keyword.clone()),
+        })
+    }
+
+    fn visit_trait(&mut self, name: &Token, methods: &[Stmt]) -> Self::Output {
+        let methods = methods
+            .iter()
+            .map(|method| self.optimize_nested(method))
+            .collect();
+        Some(Stmt::new_trait(name.clone(), methods))
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output {
+        let initializer = initializer
+            .as_ref()
+            .map(|initializer| self.optimize_expr(initializer));
+        Some(Stmt::new_var(
+            name.clone(),
+            annotation.cloned(),
+            initializer,
+        ))
+    }
+
+    fn visit_var_tuple(&mut self, names: &[Token], initializer: &Expr) -> Self::Output {
+        let initializer = self.optimize_expr(initializer);
+        Some(Stmt::new_var_tuple(names.to_vec(), initializer))
+    }
+
+    fn visit_while(&mut self, keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output {
+        let condition = self.optimize_condition(condition);
+        if let Expr::Literal { value, .. } = &condition {
+            if !is_truthy(value) {
+                return None;
+            }
+        }
+        let body = self.optimize_nested(body);
+        Some(Stmt::new_while(keyword.clone(), condition, body))
+    }
+}
+
+impl ExprVisitor for Optimizer {
+    type Output = Expr;
+
+    fn visit_assign(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        let value = self.optimize_expr(value);
+        match operator {
+            Some(operator) => Expr::new_compound_assign(id, name.clone(), operator.clone(), value),
+            None => Expr::new_assign(id, name.clone(), value),
+        }
+    }
+
+    fn visit_assign_tuple(&mut self, targets: &[(ExprId, Token)], value: &Expr) -> Self::Output {
+        Expr::new_assign_tuple(targets.to_vec(), self.optimize_expr(value))
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        let left = self.optimize_expr(left);
+        let right = self.optimize_expr(right);
+        let constant = matches!(
+            (&left, &right),
+            (Expr::Literal { .. }, Expr::Literal { .. })
+        );
+        let expr = Expr::new_binary(left, operator.clone(), right);
+        if constant {
+            self.fold(expr)
+        } else {
+            expr
+        }
+    }
+
+    fn visit_call(
+        &mut self,
+        id: ExprId,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output {
+        let callee = self.optimize_expr(callee);
+        let arguments = arguments
+            .iter()
+            .map(|argument| self.optimize_expr(argument))
+            .collect();
+        let keywords = keywords
+            .iter()
+            .map(|(name, argument)| (name.clone(), self.optimize_expr(argument)))
+            .collect();
+        Expr::new_call(id, callee, paren.clone(), arguments, keywords)
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        let condition = self.optimize_condition(condition);
+        if let Expr::Literal { value, .. } = &condition {
+            return if is_truthy(value) {
+                self.optimize_expr(then_branch)
+            } else {
+                self.optimize_expr(else_branch)
+            };
+        }
+        let then_branch = self.optimize_expr(then_branch);
+        let else_branch = self.optimize_expr(else_branch);
+        Expr::new_conditional(condition, question.clone(), then_branch, else_branch)
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> Self::Output {
+        Expr::new_get(self.optimize_expr(object), name.clone())
+    }
+
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        let object = self.optimize_expr(object);
+        let value = self.optimize_expr(value);
+        match operator {
+            Some(operator) => Expr::new_compound_set(object, name.clone(), operator.clone(), value),
+            None => Expr::new_set(object, name.clone(), value),
+        }
+    }
+
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, method: &Token) -> Self::Output {
+        Expr::new_super(id, keyword.clone(), method.clone())
+    }
+
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> Self::Output {
+        Expr::new_this(id, keyword.clone())
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> Self::Output {
+        match self.optimize_expr(expression) {
+            literal @ Expr::Literal { .. } => literal,
+            expression => Expr::new_grouping(expression),
+        }
+    }
+
+    fn visit_literal(&mut self, token: Option<&Token>, value: &TokenLiteral) -> Self::Output {
+        Expr::Literal {
+            token: token.cloned(),
+            value: value.clone(),
+        }
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        let left = self.optimize_expr(left);
+        if let Expr::Literal { value, .. } = &left {
+            // the result is whichever operand decides the outcome
+            let short_circuits = if operator.ttype == TokenType::Or {
+                is_truthy(value)
+            } else {
+                !is_truthy(value)
+            };
+            return if short_circuits {
+                left
+            } else {
+                self.optimize_expr(right)
+            };
+        }
+        Expr::new_logical(left, operator.clone(), self.optimize_expr(right))
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output {
+        Expr::new_tuple(
+            elements
+                .iter()
+                .map(|element| self.optimize_expr(element))
+                .collect(),
+        )
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Self::Output {
+        let right = if operator.ttype == TokenType::Bang {
+            self.optimize_condition(right)
+        } else {
+            self.optimize_expr(right)
+        };
+        let constant = matches!(right, Expr::Literal { .. });
+        let expr = Expr::new_unary(operator.clone(), right);
+        if constant {
+            self.fold(expr)
+        } else {
+            expr
+        }
+    }
+
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> Self::Output {
+        Expr::new_variable(id, name.clone())
+    }
+
+    fn visit_yield(&mut self, keyword: &Token, value: Option<&Expr>) -> Self::Output {
+        let value = value.map(|value| self.optimize_expr(value));
+        Expr::new_yield(keyword.clone(), value)
+    }
+}
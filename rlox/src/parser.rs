@@ -1,20 +1,36 @@
+use std::{
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use crate::{
-    expr::Expr,
+    expr::{Expr, ExprId},
     lox::Lox,
-    stmt::Stmt,
-    token::{Token, TokenType},
+    pattern::Pattern,
+    stmt::{MatchArm, Param, Stmt, TypeAnnotation},
+    token::{Token, TokenLiteral, TokenType},
 };
 
 pub struct Parser<'a> {
     tokens: &'a [Token],
     current: usize,
+    /// Whether each function being parsed, innermost last, has yielded so far.
+    generators: Vec<bool>,
 }
 
 struct ParseError;
 
+/// Source of `ExprId`s. Shared by every parser so that expressions parsed later, such as
+/// REPL lines or debugger prompts, never reuse an ID the resolver has already seen.
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
 impl Parser<'_> {
     pub fn new<'a>(tokens: &'a [Token]) -> Parser<'a> {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            generators: Vec::new(),
+        }
     }
 
     pub fn parse(&mut self) -> Vec<Option<Stmt>> {
@@ -25,13 +41,27 @@ impl Parser<'_> {
         statements
     }
 
+    /// Parses a single standalone expression, such as one typed at a debugger prompt.
+    pub fn parse_expression(&mut self) -> Option<Expr> {
+        let expr = self.expression().ok()?;
+        if !self.is_at_end() {
+            self.error(self.peek(), "Expect end of expression.");
+            return None;
+        }
+        Some(expr)
+    }
+
     fn declaration(&mut self) -> Option<Stmt> {
         let stmt: Result<Stmt, ParseError> = if self.match_token(&[TokenType::Class]) {
             self.class_declaration()
+        } else if self.match_token(&[TokenType::Trait]) {
+            self.trait_declaration()
         } else if self.match_token(&[TokenType::Fun]) {
             self.function("function")
         } else if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.match_token(&[TokenType::Const]) {
+            self.const_declaration()
         } else {
             self.statement()
         };
@@ -52,7 +82,18 @@ impl Parser<'_> {
         let mut superclass = None;
         if self.match_token(&[TokenType::Less]) {
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
-            superclass = Some(Expr::new_variable(self.previous().clone()));
+            superclass = Some(Expr::new_variable(self.next_id(), self.previous().clone()));
+        }
+
+        let mut traits = Vec::new();
+        if self.match_token(&[TokenType::With]) {
+            loop {
+                self.consume(TokenType::Identifier, "Expect trait name.")?;
+                traits.push(Expr::new_variable(self.next_id(), self.previous().clone()));
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
 
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
@@ -61,7 +102,20 @@ impl Parser<'_> {
             methods.push(self.function("method")?);
         }
         self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
-        Ok(Stmt::new_class(name, superclass, methods))
+        Ok(Stmt::new_class(name, superclass, traits, methods))
+    }
+
+    fn trait_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect trait name.")?
+            .clone();
+        self.consume(TokenType::LeftBrace, "Expect '{' before trait body.")?;
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after trait body.")?;
+        Ok(Stmt::new_trait(name, methods))
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
@@ -80,6 +134,9 @@ impl Parser<'_> {
         if self.match_token(&[TokenType::While]) {
             return self.while_stmt();
         }
+        if self.match_token(&[TokenType::Match]) {
+            return self.match_stmt();
+        }
         if self.match_token(&[TokenType::LeftBrace]) {
             return Ok(Stmt::new_block(self.block()?));
         }
@@ -88,7 +145,11 @@ impl Parser<'_> {
     }
 
     fn for_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        if self.check(TokenType::Var) && self.check_ahead(2, TokenType::In) {
+            return self.for_in_stmt(keyword);
+        }
 
         let initializer = if self.match_token(&[TokenType::Semicolon]) {
             None
@@ -108,7 +169,7 @@ impl Parser<'_> {
         let increment = if self.check(TokenType::RightParen) {
             None
         } else {
-            Some(self.expression()?)
+            Some(self.statement_expression()?)
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
@@ -121,7 +182,11 @@ impl Parser<'_> {
             ])
         }
 
-        body = Stmt::new_while(condition.unwrap_or(Expr::new_literal_bool(true)), body);
+        body = Stmt::new_while(
+            keyword,
+            condition.unwrap_or(Expr::new_literal_bool(true)),
+            body,
+        );
 
         if initializer.is_some() {
             body = Stmt::new_block(vec![initializer, Some(body)])
@@ -130,7 +195,21 @@ impl Parser<'_> {
         Ok(body)
     }
 
+    /// Parses the rest of `for (var name in iterable) body`, after the opening paren.
+    fn for_in_stmt(&mut self, keyword: Token) -> Result<Stmt, ParseError> {
+        self.advance();
+        let name = self
+            .consume(TokenType::Identifier, "Expect variable name.")?
+            .clone();
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for-in clause.")?;
+        let body = self.statement()?;
+        Ok(Stmt::new_for_in(keyword, name, iterable, body))
+    }
+
     fn if_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
@@ -142,13 +221,116 @@ impl Parser<'_> {
             None
         };
 
-        Ok(Stmt::new_if(condition, then_branch, else_branch))
+        Ok(Stmt::new_if(keyword, condition, then_branch, else_branch))
+    }
+
+    fn match_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'match'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after match subject.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before match arms.")?;
+
+        let mut arms = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let pattern = self.pattern()?;
+            let guard = if self.match_token(&[TokenType::If]) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            self.consume(TokenType::EqualGreater, "Expect '=>' after pattern.")?;
+            let body = self.statement()?;
+            // arms may be separated by commas
+            self.match_token(&[TokenType::Comma]);
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.")?;
+        Ok(Stmt::new_match(keyword, subject, arms))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        let mut alternatives = vec![self.single_pattern()?];
+        while self.match_token(&[TokenType::Pipe]) {
+            alternatives.push(self.single_pattern()?);
+        }
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Pattern::Alternatives(alternatives))
+        }
+    }
+
+    fn single_pattern(&mut self) -> Result<Pattern, ParseError> {
+        if self.match_token(&[
+            TokenType::False,
+            TokenType::True,
+            TokenType::Nil,
+            TokenType::Number,
+            TokenType::String,
+        ]) {
+            let token = self.previous().clone();
+            let value = match token.ttype {
+                TokenType::False => TokenLiteral::Bool(false),
+                TokenType::True => TokenLiteral::Bool(true),
+                _ => token.literal.clone(),
+            };
+            return Ok(Pattern::Literal { token, value });
+        }
+        if self.match_token(&[TokenType::Minus]) {
+            let token = self.previous().clone();
+            let number = self.consume(TokenType::Number, "Expect number after '-'.")?;
+            let value = match &number.literal {
+                TokenLiteral::Number(n) => TokenLiteral::Number(-*n),
+                TokenLiteral::Integer(n) => TokenLiteral::Integer(-n),
+                TokenLiteral::BigInt(n) => TokenLiteral::BigInt(Rc::new(-n.as_ref())),
+                _ => unreachable!("number tokens hold numbers"),
+            };
+            return Ok(Pattern::Literal { token, value });
+        }
+
+        let name = self
+            .consume(TokenType::Identifier, "Expect pattern.")?
+            .clone();
+        if name.lexeme == "_" {
+            return Ok(Pattern::Wildcard(name));
+        }
+        if !self.match_token(&[TokenType::LeftParen]) {
+            return Ok(Pattern::Binding(name));
+        }
+        let mut fields = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                let field = self
+                    .consume(TokenType::Identifier, "Expect field name.")?
+                    .clone();
+                let pattern = if self.match_token(&[TokenType::Colon]) {
+                    self.pattern()?
+                } else {
+                    Pattern::Binding(field.clone())
+                };
+                fields.push((field, pattern));
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after field patterns.")?;
+        Ok(Pattern::Instance {
+            class: Expr::new_variable(self.next_id(), name),
+            fields,
+        })
     }
 
     fn print_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::new_print(value))
+        Ok(Stmt::new_print(keyword, value))
     }
 
     fn return_stmt(&mut self) -> Result<Stmt, ParseError> {
@@ -156,7 +338,12 @@ impl Parser<'_> {
         let value = if self.check(TokenType::Semicolon) {
             Expr::new_literal_nil()
         } else {
-            self.expression()?
+            let value = self.expression()?;
+            if self.check(TokenType::Comma) {
+                self.tuple(value)?
+            } else {
+                value
+            }
         };
         self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
         Ok(Stmt::new_return(keyword, value))
@@ -170,17 +357,45 @@ impl Parser<'_> {
             TokenType::LeftParen,
             &format!("Expect '(' after {} name.", kind),
         )?;
-        let mut parameters = Vec::new();
+        let mut parameters: Vec<Param> = Vec::new();
+        let mut rest = None;
         if !self.check(TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
                     self.error(self.peek(), "Can't have more than 255 parameters.");
                 }
 
-                parameters.push(
-                    self.consume(TokenType::Identifier, "Expect parameter name.")?
-                        .clone(),
-                );
+                if self.match_token(&[TokenType::DotDotDot]) {
+                    let name = self.consume(TokenType::Identifier, "Expect parameter name.")?;
+                    rest = Some(name.clone());
+                    if self.check(TokenType::Comma) {
+                        return Err(self.error(self.peek(), "Rest parameter must be the last one."));
+                    }
+                    break;
+                }
+                let name = self
+                    .consume(TokenType::Identifier, "Expect parameter name.")?
+                    .clone();
+                let annotation = self.type_annotation()?;
+                let default = if self.match_token(&[TokenType::Equal]) {
+                    Some(self.expression()?)
+                } else {
+                    if parameters
+                        .last()
+                        .is_some_and(|param| param.default.is_some())
+                    {
+                        self.error(
+                            &name,
+                            "Parameter without a default value can't follow one with a default.",
+                        );
+                    }
+                    None
+                };
+                parameters.push(Param {
+                    name,
+                    annotation,
+                    default,
+                });
 
                 if !self.match_token(&[TokenType::Comma]) {
                     break;
@@ -189,19 +404,33 @@ impl Parser<'_> {
         }
 
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        let return_type = self.type_annotation()?;
 
         self.consume(
             TokenType::LeftBrace,
             &format!("Expect '{{' before {} body.", kind),
         )?;
-        let body = self.block()?;
-        Ok(Stmt::new_function(name, parameters, body))
+        self.generators.push(false);
+        let body = self.block();
+        let is_generator = self.generators.pop().unwrap();
+        Ok(Stmt::new_function(
+            name,
+            parameters,
+            rest,
+            return_type,
+            body?,
+            is_generator,
+        ))
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(&[TokenType::LeftParen]) {
+            return self.var_tuple_declaration();
+        }
         let name = self
             .consume(TokenType::Identifier, "Expect variable name.")?
             .clone();
+        let annotation = self.type_annotation()?;
         let mut initializer = None;
         if self.match_token(&[TokenType::Equal]) {
             initializer = Some(self.expression()?);
@@ -210,20 +439,67 @@ impl Parser<'_> {
             TokenType::Semicolon,
             "Expect ';' after variable declaration.",
         )?;
-        Ok(Stmt::new_var(name.clone(), initializer))
+        Ok(Stmt::new_var(name.clone(), annotation, initializer))
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect constant name.")?
+            .clone();
+        let annotation = self.type_annotation()?;
+        self.consume(TokenType::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        )?;
+        Ok(Stmt::new_const(name, annotation, initializer))
+    }
+
+    /// Parses the `: Type` after a declared name, if there is one.
+    fn type_annotation(&mut self) -> Result<Option<TypeAnnotation>, ParseError> {
+        if !self.match_token(&[TokenType::Colon]) {
+            return Ok(None);
+        }
+        let name = self.consume(TokenType::Identifier, "Expect type name after ':'.")?;
+        Ok(Some(TypeAnnotation { name: name.clone() }))
+    }
+
+    /// Parses the rest of `var (a, b) = initializer;`.
+    fn var_tuple_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let mut names = Vec::new();
+        loop {
+            let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+            names.push(name.clone());
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after variable names.")?;
+        self.consume(
+            TokenType::Equal,
+            "Expect '=' after variable names to destructure.",
+        )?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::new_var_tuple(names, initializer))
     }
 
     fn while_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
         let body = self.statement()?;
 
-        Ok(Stmt::new_while(condition, body))
+        Ok(Stmt::new_while(keyword, condition, body))
     }
 
     fn expression_stmt(&mut self) -> Result<Stmt, ParseError> {
-        let expr = self.expression()?;
+        let expr = self.statement_expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
         Ok(Stmt::new_expression(expr))
     }
@@ -242,23 +518,120 @@ impl Parser<'_> {
         self.assignment()
     }
 
-    fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or()?;
+    /// Parses an expression whose value is discarded, which can also be an increment or
+    /// decrement such as `i++`.
+    fn statement_expression(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.expression()?;
 
-        if self.match_token(&[TokenType::Equal]) {
+        if self.match_token(&[TokenType::MinusMinus, TokenType::PlusPlus]) {
+            let operator = self.previous().clone();
+            let one = Expr::new_literal(TokenLiteral::Integer(1));
+            return self.assign_to(expr, &operator, one);
+        }
+
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        if self.match_token(&[TokenType::Yield]) {
+            return self.yield_expr();
+        }
+        let expr = self.conditional()?;
+
+        if self.match_token(&[
+            TokenType::Equal,
+            TokenType::MinusEqual,
+            TokenType::PercentEqual,
+            TokenType::PlusEqual,
+            TokenType::SlashEqual,
+            TokenType::StarEqual,
+        ]) {
             let equals = self.previous().clone();
             let value = self.assignment()?;
-            match expr {
-                Expr::Variable { name } => {
-                    return Ok(Expr::new_assign(name, value));
-                }
-                Expr::Get { object, name } => {
-                    return Ok(Expr::new_set(*object, name, value));
+            return self.assign_to(expr, &equals, value);
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses the rest of `yield value`, which turns the enclosing function into a generator.
+    /// The resolver checks that it is used where a generator can suspend.
+    fn yield_expr(&mut self) -> Result<Expr, ParseError> {
+        let keyword = self.previous().clone();
+        if let Some(is_generator) = self.generators.last_mut() {
+            *is_generator = true;
+        }
+        let value = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        Ok(Expr::new_yield(keyword, value))
+    }
+
+    /// Builds an assignment of `value` to `target`. If `equals` is a compound assignment
+    /// operator such as `+=`, or `++`/`--`, the current value of the target is combined
+    /// with `value` first.
+    fn assign_to(&mut self, target: Expr, equals: &Token, value: Expr) -> Result<Expr, ParseError> {
+        let operator = match equals.ttype {
+            TokenType::Equal => None,
+            TokenType::MinusEqual | TokenType::MinusMinus => Some((TokenType::Minus, "-")),
+            TokenType::PercentEqual => Some((TokenType::Percent, "%")),
+            TokenType::PlusEqual | TokenType::PlusPlus => Some((TokenType::Plus, "+")),
+            TokenType::SlashEqual => Some((TokenType::Slash, "/")),
+            TokenType::StarEqual => Some((TokenType::Star, "*")),
+            _ => unreachable!("not an assignment operator"),
+        }
+        .map(|(ttype, lexeme)| {
+            Token::new(
+                ttype,
+                lexeme,
+                &TokenLiteral::Nil,
+                equals.line,
+                equals.cursor,
+            )
+        });
+
+        match (target, operator) {
+            (Expr::Variable { id, name }, None) => Ok(Expr::new_assign(id, name, value)),
+            (Expr::Variable { id, name }, Some(operator)) => {
+                Ok(Expr::new_compound_assign(id, name, operator, value))
+            }
+            (Expr::Get { object, name }, None) => Ok(Expr::new_set(*object, name, value)),
+            (Expr::Get { object, name }, Some(operator)) => {
+                Ok(Expr::new_compound_set(*object, name, operator, value))
+            }
+            (Expr::Tuple { elements }, None) => {
+                let mut targets = Vec::new();
+                for element in elements {
+                    match element {
+                        Expr::Variable { id, name } => targets.push((id, name)),
+                        _ => return Err(self.error(equals, "Invalid assignment target")),
+                    }
                 }
-                _ => {}
+                Ok(Expr::new_assign_tuple(targets, value))
             }
+            _ => Err(self.error(equals, "Invalid assignment target")),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.or()?;
 
-            return Err(self.error(&equals, "Invalid assignment target"));
+        if self.match_token(&[TokenType::Question]) {
+            let question = self.previous().clone();
+            let then_branch = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            let else_branch = self.conditional()?;
+            return Ok(Expr::new_conditional(
+                expr,
+                question,
+                then_branch,
+                else_branch,
+            ));
         }
 
         Ok(expr)
@@ -301,7 +674,7 @@ impl Parser<'_> {
     }
 
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.term()?;
+        let mut expr = self.bit_or()?;
 
         while self.match_token(&[
             TokenType::Greater,
@@ -309,6 +682,57 @@ impl Parser<'_> {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            let operator = self.previous().clone();
+            let right = self.bit_or()?;
+            expr = Expr::new_binary(expr, operator, right);
+        }
+
+        Ok(expr)
+    }
+
+    // The bitwise operators bind tighter than comparisons, so `a & mask == 0` tests the
+    // masked value.
+
+    fn bit_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.match_token(&[TokenType::Pipe]) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = Expr::new_binary(expr, operator, right);
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.bit_and()?;
+
+        while self.match_token(&[TokenType::Caret]) {
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = Expr::new_binary(expr, operator, right);
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.shift()?;
+
+        while self.match_token(&[TokenType::Ampersand]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = Expr::new_binary(expr, operator, right);
+        }
+
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.term()?;
+
+        while self.match_token(&[TokenType::GreaterGreater, TokenType::LessLess]) {
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expr::new_binary(expr, operator, right);
@@ -332,7 +756,12 @@ impl Parser<'_> {
     fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
 
-        while self.match_token(&[TokenType::Slash, TokenType::Star]) {
+        while self.match_token(&[
+            TokenType::Percent,
+            TokenType::Slash,
+            TokenType::Star,
+            TokenType::TildeSlash,
+        ]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expr::new_binary(expr, operator, right);
@@ -342,13 +771,28 @@ impl Parser<'_> {
     }
 
     fn unary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
+        if self.match_token(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
             return Ok(Expr::new_unary(operator, right));
         }
 
-        self.call()
+        self.power()
+    }
+
+    /// `**` binds tighter than the unary operators on its left but not the ones on its
+    /// right, so `-2 ** 2` is `-(2 ** 2)` and `2 ** -1` is `2 ** (-1)`. It is
+    /// right-associative: `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.call()?;
+
+        if self.match_token(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            return Ok(Expr::new_binary(expr, operator, right));
+        }
+
+        Ok(expr)
     }
 
     fn call(&mut self) -> Result<Expr, ParseError> {
@@ -369,12 +813,31 @@ impl Parser<'_> {
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
         let mut arguments = Vec::new();
+        let mut keywords: Vec<(Token, Expr)> = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
-                if arguments.len() >= 255 {
+                if arguments.len() + keywords.len() >= 255 {
                     self.error(self.peek(), "Can't have more than 255 arguments.");
                 }
-                arguments.push(self.expression()?);
+                if self.check(TokenType::Identifier) && self.check_ahead(1, TokenType::Colon) {
+                    let name = self.advance().clone();
+                    self.advance();
+                    if keywords
+                        .iter()
+                        .any(|(other, _)| other.lexeme == name.lexeme)
+                    {
+                        self.error(&name, "Keyword argument given twice.");
+                    }
+                    keywords.push((name, self.expression()?));
+                } else {
+                    if !keywords.is_empty() {
+                        self.error(
+                            self.peek(),
+                            "Positional argument can't follow keyword arguments.",
+                        );
+                    }
+                    arguments.push(self.expression()?);
+                }
                 if !self.match_token(&[TokenType::Comma]) {
                     break;
                 }
@@ -382,25 +845,41 @@ impl Parser<'_> {
         }
         let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
 
-        Ok(Expr::new_call(callee, paren.clone(), arguments))
+        let paren = paren.clone();
+        Ok(Expr::new_call(
+            self.next_id(),
+            callee,
+            paren,
+            arguments,
+            keywords,
+        ))
     }
 
     fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_token(&[TokenType::False]) {
-            return Ok(Expr::new_literal_bool(false));
-        }
-        if self.match_token(&[TokenType::True]) {
-            return Ok(Expr::new_literal_bool(true));
-        }
-        if self.match_token(&[TokenType::Nil]) {
-            return Ok(Expr::new_literal_nil());
-        }
-        if self.match_token(&[TokenType::Number, TokenType::String]) {
-            return Ok(Expr::new_literal(self.previous().literal.clone()));
+        if self.match_token(&[
+            TokenType::False,
+            TokenType::True,
+            TokenType::Nil,
+            TokenType::Number,
+            TokenType::String,
+        ]) {
+            let token = self.previous().clone();
+            let value = match token.ttype {
+                TokenType::False => TokenLiteral::Bool(false),
+                TokenType::True => TokenLiteral::Bool(true),
+                TokenType::Nil => TokenLiteral::Nil,
+                _ => token.literal.clone(),
+            };
+            return Ok(Expr::new_literal_at(token, value));
         }
 
         if self.match_token(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
+            if self.check(TokenType::Comma) {
+                let tuple = self.tuple(expr)?;
+                self.consume(TokenType::RightParen, "Expect ')' after tuple elements.")?;
+                return Ok(tuple);
+            }
             self.consume(TokenType::RightParen, "Expect ')' after expression")?;
             return Ok(Expr::new_grouping(expr));
         }
@@ -408,13 +887,14 @@ impl Parser<'_> {
             let keyword = self.previous().clone();
             self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
             let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
-            return Ok(Expr::new_super(keyword, method.clone()));
+            let method = method.clone();
+            return Ok(Expr::new_super(self.next_id(), keyword, method));
         }
         if self.match_token(&[TokenType::This]) {
-            return Ok(Expr::new_this(self.previous().clone()));
+            return Ok(Expr::new_this(self.next_id(), self.previous().clone()));
         }
         if self.match_token(&[TokenType::Identifier]) {
-            return Ok(Expr::new_variable(self.previous().clone()));
+            return Ok(Expr::new_variable(self.next_id(), self.previous().clone()));
         }
 
         Err(self.error(self.peek(), "Expect expression"))
@@ -445,6 +925,13 @@ impl Parser<'_> {
         self.peek().ttype == ttype
     }
 
+    /// Whether the token `offset` places after the current one has type `ttype`.
+    fn check_ahead(&self, offset: usize, ttype: TokenType) -> bool {
+        self.tokens
+            .get(self.current + offset)
+            .is_some_and(|token| token.ttype == ttype)
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -464,6 +951,19 @@ impl Parser<'_> {
         &self.tokens[self.current - 1]
     }
 
+    /// Parses the comma-separated elements of a tuple after its first one.
+    fn tuple(&mut self, first: Expr) -> Result<Expr, ParseError> {
+        let mut elements = vec![first];
+        while self.match_token(&[TokenType::Comma]) {
+            elements.push(self.expression()?);
+        }
+        Ok(Expr::new_tuple(elements))
+    }
+
+    fn next_id(&self) -> ExprId {
+        NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
     fn error(&self, token: &Token, msg: &str) -> ParseError {
         Lox::error_on_token(token, msg);
         ParseError
@@ -478,13 +978,17 @@ impl Parser<'_> {
             }
             match self.peek().ttype {
                 TokenType::Class
+                | TokenType::Const
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
+                | TokenType::Match
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => {
+                | TokenType::Return
+                | TokenType::Trait
+                | TokenType::Yield => {
                     return;
                 }
                 _ => {}
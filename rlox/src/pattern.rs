@@ -0,0 +1,78 @@
+use crate::{
+    expr::Expr,
+    token::{Token, TokenLiteral},
+};
+
+/// What a `match` arm compares its subject with.
+#[derive(Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, which matches anything.
+    Wildcard(Token),
+    /// A literal such as `1`, `-2.5`, `"a"` or `nil`, which matches values equal to it.
+    Literal { token: Token, value: TokenLiteral },
+    /// A name, which matches anything and binds it to a new variable.
+    Binding(Token),
+    /// `a | b`, which matches whatever one of its alternatives does. Alternatives can't
+    /// bind variables.
+    Alternatives(Vec<Pattern>),
+    /// `Point(x, y: 0)`, which matches instances of the class or a subclass whose fields
+    /// match the patterns given for them. A field named on its own is bound to a variable
+    /// of the same name.
+    Instance {
+        class: Expr,
+        fields: Vec<(Token, Pattern)>,
+    },
+}
+
+impl Pattern {
+    /// The token the pattern starts with, to report it at.
+    pub fn token(&self) -> &Token {
+        match self {
+            Self::Wildcard(token) | Self::Literal { token, .. } | Self::Binding(token) => token,
+            Self::Alternatives(alternatives) => alternatives[0].token(),
+            Self::Instance { class, .. } => match class {
+                Expr::Variable { name, .. } => name,
+                _ => unreachable!("instance patterns name their class"),
+            },
+        }
+    }
+
+    /// Variables the pattern binds, in the order they are defined.
+    pub fn bindings(&self) -> Vec<&Token> {
+        match self {
+            Self::Wildcard(_) | Self::Literal { .. } => Vec::new(),
+            Self::Binding(name) => vec![name],
+            Self::Alternatives(alternatives) => {
+                alternatives.iter().flat_map(Self::bindings).collect()
+            }
+            Self::Instance { fields, .. } => fields
+                .iter()
+                .flat_map(|(_, pattern)| pattern.bindings())
+                .collect(),
+        }
+    }
+
+    /// Whether the pattern matches every value.
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Self::Wildcard(_) | Self::Binding(_) => true,
+            Self::Alternatives(alternatives) => alternatives.iter().any(Self::is_irrefutable),
+            Self::Literal { .. } | Self::Instance { .. } => false,
+        }
+    }
+
+    /// The values the pattern matches, if it only matches literals.
+    pub fn literals(&self) -> Option<Vec<&TokenLiteral>> {
+        match self {
+            Self::Literal { value, .. } => Some(vec![value]),
+            Self::Alternatives(alternatives) => {
+                let mut literals = Vec::new();
+                for alternative in alternatives {
+                    literals.extend(alternative.literals()?);
+                }
+                Some(literals)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hook::Hook, interpreter::Interpreter, lox_callable::LoxCallable, stmt::Stmt, value::Value,
+};
+
+/// Number of rows shown in the hottest lines table.
+const HOT_LINES: usize = 10;
+
+/// Accumulated measurements for one function, native or class constructor.
+struct FunctionStats {
+    label: String,
+    kind: &'static str,
+    calls: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+    /// Number of frames for this function currently on the stack, so recursive calls
+    /// only count towards inclusive time once.
+    active: usize,
+}
+
+/// A call that has not returned yet.
+struct ProfileFrame {
+    stats: usize,
+    start: Instant,
+    children: Duration,
+    /// `;`-separated labels from the script down to this frame.
+    path: String,
+}
+
+/// Hook that measures where a script spends its time.
+///
+/// Prints a table of per-function timings and the most executed lines to stderr once the
+/// program finishes, and optionally writes folded stacks for flamegraph tools.
+pub struct Profiler {
+    functions: Vec<FunctionStats>,
+    indices: HashMap<(&'static str, String), usize>,
+    stack: Vec<ProfileFrame>,
+    lines: HashMap<usize, u64>,
+    folded: HashMap<String, Duration>,
+    folded_path: Option<String>,
+}
+
+impl Profiler {
+    pub fn new(folded_path: Option<String>) -> Self {
+        Self {
+            functions: Vec::new(),
+            indices: HashMap::new(),
+            stack: Vec::new(),
+            lines: HashMap::new(),
+            folded: HashMap::new(),
+            folded_path,
+        }
+    }
+
+    fn stats_index(&mut self, kind: &'static str, label: String) -> usize {
+        if let Some(index) = self.indices.get(&(kind, label.clone())) {
+            return *index;
+        }
+        self.functions.push(FunctionStats {
+            label: label.clone(),
+            kind,
+            calls: 0,
+            inclusive: Duration::ZERO,
+            exclusive: Duration::ZERO,
+            active: 0,
+        });
+        self.indices.insert((kind, label), self.functions.len() - 1);
+        self.functions.len() - 1
+    }
+
+    fn push(&mut self, kind: &'static str, label: String) {
+        let index = self.stats_index(kind, label.clone());
+        let stats = &mut self.functions[index];
+        stats.calls += 1;
+        stats.active += 1;
+        let path = match self.stack.last() {
+            Some(parent) => format!("{};{}", parent.path, label),
+            None => label,
+        };
+        self.stack.push(ProfileFrame {
+            stats: index,
+            start: Instant::now(),
+            children: Duration::ZERO,
+            path,
+        });
+    }
+
+    fn pop(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = elapsed.saturating_sub(frame.children);
+
+        let stats = &mut self.functions[frame.stats];
+        stats.active -= 1;
+        if stats.active == 0 {
+            stats.inclusive += elapsed;
+        }
+        stats.exclusive += exclusive;
+        *self.folded.entry(frame.path).or_default() += exclusive;
+
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+
+    fn ensure_started(&mut self) {
+        if self.stack.is_empty() {
+            self.push("script", "<script>".into());
+        }
+    }
+
+    fn report(&self) {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|stats| Reverse(stats.exclusive));
+
+        let mut out = io::stderr();
+        writeln!(
+            out,
+            "{:<24} {:<7} {:>10} {:>12} {:>12}",
+            "function", "kind", "calls", "incl ms", "excl ms"
+        )
+        .unwrap();
+        for stats in functions {
+            writeln!(
+                out,
+                "{:<24} {:<7} {:>10} {:>12.3} {:>12.3}",
+                stats.label,
+                stats.kind,
+                stats.calls,
+                stats.inclusive.as_secs_f64() * 1000.0,
+                stats.exclusive.as_secs_f64() * 1000.0,
+            )
+            .unwrap();
+        }
+
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(out).unwrap();
+        writeln!(out, "{:<8} {:>10}", "line", "executed").unwrap();
+        for (line, count) in lines.into_iter().take(HOT_LINES) {
+            writeln!(out, "{:<8} {:>10}", line, count).unwrap();
+        }
+    }
+
+    fn write_folded(&self, path: &str) {
+        let mut stacks: Vec<_> = self.folded.iter().collect();
+        stacks.sort();
+        let mut content = String::new();
+        for (stack, time) in stacks {
+            content.push_str(&format!("{} {}\n", stack, time.as_micros()));
+        }
+        if let Err(e) = fs::write(path, content) {
+            eprintln!("Could not write folded stacks to '{}': {}", path, e);
+        }
+    }
+}
+
+impl Hook for Profiler {
+    fn on_statement(&mut self, _interpreter: &mut Interpreter, statement: &Stmt) {
+        self.ensure_started();
+        if let Some(line) = statement.line() {
+            *self.lines.entry(line).or_default() += 1;
+        }
+    }
+
+    fn on_call(&mut self, _interpreter: &mut Interpreter, callee: &Value) {
+        self.ensure_started();
+        let (kind, label) = match callee {
+            Value::LoxFn(f) => ("fn", format!("{}:{}", f.name(), f.line())),
+            Value::LoxClass(c) => ("class", c.name()),
+            Value::NativeFn(f) => ("native", f.name()),
+            Value::NativeMethod(f) => ("native", f.name()),
+            _ => unreachable!("only callable values are called"),
+        };
+        self.push(kind, label);
+    }
+
+    fn on_return(&mut self, _interpreter: &mut Interpreter, _callee: &Value) {
+        self.pop();
+    }
+
+    fn on_finish(&mut self, _interpreter: &mut Interpreter) {
+        while !self.stack.is_empty() {
+            self.pop();
+        }
+        self.report();
+        if let Some(path) = &self.folded_path {
+            self.write_folded(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::lox::{Lox, Options};
+
+    #[test]
+    fn test_folded_stacks() {
+        let source = "fun inner() { return 1; }
+fun outer() { return inner() + inner(); }
+outer();
+";
+        let path = env::temp_dir().join(format!("rlox-folded-{}.txt", std::process::id()));
+        let options = Options {
+            profile: true,
+            profile_folded: Some(path.to_string_lossy().into()),
+            ..Options::default()
+        };
+        Lox::run(source, "<test>", &options);
+        let folded = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // each line is a call stack followed by its exclusive time in microseconds
+        let stacks: Vec<&str> = folded
+            .lines()
+            .map(|line| {
+                let (stack, time) = line.rsplit_once(' ').unwrap();
+                assert!(time.parse::<u128>().is_ok(), "bad time in '{}'", line);
+                stack
+            })
+            .collect();
+        assert_eq!(
+            stacks,
+            ["<script>", "<script>;outer:2", "<script>;outer:2;inner:1"]
+        );
+    }
+}
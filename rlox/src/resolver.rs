@@ -1,18 +1,36 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
 
 use crate::{
-    expr::{Expr, ExprVisitor},
+    expr::{Expr, ExprId, ExprVisitor},
     interpreter::Interpreter,
     lox::Lox,
-    stmt::{Stmt, StmtVisitor},
+    pattern::Pattern,
+    stmt::{FunctionDecl, MatchArm, Stmt, StmtVisitor, TypeAnnotation},
     token::{Token, TokenLiteral},
 };
 
 pub struct Resolver {
     interpreter: Rc<RefCell<Interpreter>>,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Local>>,
     current_function: FunctionType,
     current_class: ClassType,
+    /// Whether the function being resolved is a generator.
+    in_generator: bool,
+    /// Whether the statement being resolved starts with a `yield`, which is then allowed.
+    yield_allowed: bool,
+    /// Whether function and class names are constants.
+    strict: bool,
+}
+
+/// A variable declared in a local scope.
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    /// Index of the variable's slot in its `Environment`.
+    slot: usize,
+    /// Whether the initializer has been resolved, so the variable may be read.
+    defined: bool,
+    /// Whether the variable was declared `const`, so it can't be assigned.
+    constant: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -28,6 +46,8 @@ enum ClassType {
     None,
     Class,
     Subclass,
+    /// A trait, in whose methods `super` is the superclass of the class using the trait.
+    Trait,
 }
 
 impl Resolver {
@@ -37,9 +57,17 @@ impl Resolver {
             scopes: Vec::new(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            in_generator: false,
+            yield_allowed: false,
+            strict: false,
         }
     }
 
+    /// Makes function and class names constants, so assigning to them is an error.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
     pub fn resolve_stmt_opts(&mut self, statements: &[Option<Stmt>]) {
         for stmt in statements {
             match stmt {
@@ -50,6 +78,7 @@ impl Resolver {
     }
 
     fn resolve_stmt(&mut self, stmt: &Stmt) {
+        self.yield_allowed = stmt.first_yield().is_some();
         stmt.accept_visitor(self);
     }
 
@@ -57,29 +86,42 @@ impl Resolver {
         expr.accept_visitor(self);
     }
 
-    fn resolve_local(&mut self, expr: &Expr, name: &Token) {
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
         for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains_key(&name.lexeme) {
+            if let Some(local) = self.scopes[i].get(&name.lexeme) {
                 let depth = self.scopes.len() - 1 - i;
-                self.interpreter.as_ref().borrow_mut().resolve(expr, depth);
+                self.interpreter
+                    .as_ref()
+                    .borrow_mut()
+                    .resolve(id, depth, local.slot);
                 return;
             }
         }
     }
 
-    fn resolve_function(&mut self, fparams: &[Token], fbody: &[Option<Stmt>], ftype: FunctionType) {
+    fn resolve_function(&mut self, declaration: &FunctionDecl, ftype: FunctionType) {
         let enclosing_function = self.current_function;
         self.current_function = ftype;
+        let enclosing_generator = self.in_generator;
+        self.in_generator = declaration.is_generator;
 
         self.begin_scope();
-        for param in fparams {
-            self.declare(param);
-            self.define(param);
+        for param in &declaration.params {
+            self.declare(&param.name);
+            if let Some(default) = &param.default {
+                self.resolve_expr(default);
+            }
+            self.define(&param.name);
+        }
+        if let Some(rest) = &declaration.rest {
+            self.declare(rest);
+            self.define(rest);
         }
-        self.resolve_stmt_opts(fbody);
+        self.resolve_stmt_opts(&declaration.body);
         self.end_scope();
 
         self.current_function = enclosing_function;
+        self.in_generator = enclosing_generator;
     }
 
     fn begin_scope(&mut self) {
@@ -97,18 +139,119 @@ impl Resolver {
         let scope = self.scopes.last_mut().unwrap();
         if scope.contains_key(&name.lexeme) {
             Lox::error_on_token(name, "Already a variable with this name in this scope.");
+            return;
         }
-        scope.insert(name.lexeme.clone(), false);
+        let slot = scope.len();
+        scope.insert(
+            name.lexeme.clone(),
+            Local {
+                slot,
+                defined: false,
+                constant: false,
+            },
+        );
     }
 
     fn define(&mut self, name: &Token) {
-        if self.scopes.is_empty() {
-            return;
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
         }
-        self.scopes
+    }
+
+    /// Marks a variable in the innermost scope as a constant.
+    fn define_const(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
             .last_mut()
-            .unwrap()
-            .insert(name.lexeme.clone(), true);
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.constant = true;
+        }
+    }
+
+    /// Reports an assignment to a local constant. Global constants are only known at
+    /// runtime, so the `Environment` checks those.
+    fn check_assignable(&self, name: &Token) {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme));
+        if local.is_some_and(|local| local.constant) {
+            Lox::error_on_token(name, "Can't assign to a constant.");
+        }
+    }
+
+    /// Resolves the classes a pattern names, which are looked up outside the arm's scope.
+    fn resolve_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard(_) | Pattern::Literal { .. } | Pattern::Binding(_) => {}
+            Pattern::Alternatives(alternatives) => {
+                if let Some(name) = pattern.bindings().first() {
+                    Lox::error_on_token(name, "Can't bind variables in alternative patterns.");
+                }
+                for alternative in alternatives {
+                    self.resolve_pattern(alternative);
+                }
+            }
+            Pattern::Instance { class, fields } => {
+                self.resolve_expr(class);
+                for (_, pattern) in fields {
+                    self.resolve_pattern(pattern);
+                }
+            }
+        }
+    }
+
+    /// Warns about arms that can never be selected because earlier ones match everything
+    /// they do, and about a `match` that can run out of arms.
+    fn check_arms(keyword: &Token, arms: &[MatchArm]) {
+        let mut seen = Vec::new();
+        let mut exhaustive = false;
+        for arm in arms {
+            let literals = arm.pattern.literals();
+            let covered = literals
+                .as_ref()
+                .is_some_and(|literals| literals.iter().all(|literal| seen.contains(literal)));
+            if exhaustive || covered {
+                Lox::warning_on_token(arm.pattern.token(), "Unreachable match arm.");
+                continue;
+            }
+            if arm.guard.is_some() {
+                continue;
+            }
+            if arm.pattern.is_irrefutable() {
+                exhaustive = true;
+            } else if let Some(literals) = literals {
+                seen.extend(literals);
+                exhaustive = seen.contains(&&TokenLiteral::Bool(true))
+                    && seen.contains(&&TokenLiteral::Bool(false));
+            }
+        }
+        if !exhaustive {
+            Lox::warning_on_token(
+                keyword,
+                "Match isn't exhaustive. Add a '_' arm to handle other values.",
+            );
+        }
+    }
+
+    /// Declares and defines a variable the interpreter binds implicitly, like `this`.
+    fn define_implicit(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        let slot = scope.len();
+        scope.insert(
+            name.into(),
+            Local {
+                slot,
+                defined: true,
+                constant: false,
+            },
+        );
     }
 }
 
@@ -125,6 +268,7 @@ impl StmtVisitor for Resolver {
         &mut self,
         name: &Token,
         superclass: &Option<Expr>,
+        traits: &[Expr],
         methods: &Vec<Stmt>,
     ) -> Self::Output {
         let enclosing_class = self.current_class;
@@ -132,10 +276,14 @@ impl StmtVisitor for Resolver {
 
         self.declare(name);
         self.define(name);
+        if self.strict {
+            self.define_const(name);
+        }
 
         match superclass {
             Some(Expr::Variable {
                 name: superclass_name,
+                ..
             }) => {
                 if name == superclass_name {
                     Lox::error_on_token(superclass_name, "A class can't inherit from itself.");
@@ -145,24 +293,38 @@ impl StmtVisitor for Resolver {
             None => {}
         }
 
+        let mut trait_names = Vec::new();
+        for used in traits {
+            if let Expr::Variable {
+                name: trait_name, ..
+            } = used
+            {
+                if trait_names.contains(&&trait_name.lexeme) {
+                    Lox::error_on_token(trait_name, "Trait is already used by this class.");
+                }
+                trait_names.push(&trait_name.lexeme);
+            }
+            self.resolve_expr(used);
+        }
+
         if let Some(superclass) = superclass {
             self.current_class = ClassType::Subclass;
             self.resolve_expr(superclass);
             self.begin_scope();
-            self.scopes.last_mut().unwrap().insert("super".into(), true);
+            self.define_implicit("super");
         }
 
         self.begin_scope();
-        self.scopes.last_mut().unwrap().insert("this".into(), true);
+        self.define_implicit("this");
 
         for method in methods {
             match method {
-                Stmt::Function { name, params, body } => {
-                    let mut declaration = FunctionType::Method;
-                    if name.lexeme == "init" {
-                        declaration = FunctionType::Initializer;
+                Stmt::Function { declaration } => {
+                    let mut function_type = FunctionType::Method;
+                    if declaration.name.lexeme == "init" {
+                        function_type = FunctionType::Initializer;
                     }
-                    self.resolve_function(&params, &body, declaration);
+                    self.resolve_function(declaration, function_type);
                 }
                 _ => {
                     unreachable!();
@@ -179,23 +341,50 @@ impl StmtVisitor for Resolver {
         self.current_class = enclosing_class;
     }
 
+    fn visit_const(
+        &mut self,
+        name: &Token,
+        _annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output {
+        self.declare(name);
+        self.resolve_expr(initializer);
+        self.define(name);
+        self.define_const(name);
+    }
+
     fn visit_expression(&mut self, expression: &Expr) -> Self::Output {
         self.resolve_expr(expression);
     }
 
-    fn visit_function(
+    fn visit_for_in(
         &mut self,
+        _keyword: &Token,
         name: &Token,
-        params: &[Token],
-        body: &[Option<Stmt>],
+        iterable: &Expr,
+        body: &Stmt,
     ) -> Self::Output {
+        self.resolve_expr(iterable);
+        // each iteration binds the variable in a scope of its own
+        self.begin_scope();
         self.declare(name);
         self.define(name);
-        self.resolve_function(params, body, FunctionType::Function);
+        self.resolve_stmt(body);
+        self.end_scope();
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output {
+        self.declare(&declaration.name);
+        self.define(&declaration.name);
+        if self.strict {
+            self.define_const(&declaration.name);
+        }
+        self.resolve_function(declaration, FunctionType::Function);
     }
 
     fn visit_if(
         &mut self,
+        _keyword: &Token,
         condition: &Expr,
         then_branch: &Stmt,
         else_branch: &Option<Stmt>,
@@ -207,7 +396,26 @@ impl StmtVisitor for Resolver {
         }
     }
 
-    fn visit_print(&mut self, expression: &Expr) -> Self::Output {
+    fn visit_match(&mut self, keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output {
+        self.resolve_expr(subject);
+        Self::check_arms(keyword, arms);
+        for arm in arms {
+            self.resolve_pattern(&arm.pattern);
+            // each arm binds its variables in a scope of its own, shared with its guard
+            self.begin_scope();
+            for name in arm.pattern.bindings() {
+                self.declare(name);
+                self.define(name);
+            }
+            if let Some(guard) = &arm.guard {
+                self.resolve_expr(guard);
+            }
+            self.resolve_stmt(&arm.body);
+            self.end_scope();
+        }
+    }
+
+    fn visit_print(&mut self, _keyword: &Token, expression: &Expr) -> Self::Output {
         self.resolve_expr(expression);
     }
 
@@ -219,11 +427,49 @@ impl StmtVisitor for Resolver {
             if self.current_function == FunctionType::Initializer {
                 Lox::error_on_token(keyword, "Can't return a value from a initializer.");
             }
+            // a generator's frame outlives its calls, so there is nothing to reuse
+            if let (Expr::Call { id, .. }, false) = (value.as_ref(), self.in_generator) {
+                self.interpreter.borrow_mut().mark_tail_call(*id);
+            }
             self.resolve_expr(&value);
         }
     }
 
-    fn visit_var(&mut self, name: &Token, initializer: &Option<Expr>) -> Self::Output {
+    fn visit_trait(&mut self, name: &Token, methods: &[Stmt]) -> Self::Output {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Trait;
+
+        self.declare(name);
+        self.define(name);
+        if self.strict {
+            self.define_const(name);
+        }
+
+        // the scopes a mixed in method runs in, see `LoxTrait::methods_for`
+        self.begin_scope();
+        self.define_implicit("super");
+        self.begin_scope();
+        self.define_implicit("this");
+        for method in methods {
+            if let Stmt::Function { declaration } = method {
+                if declaration.name.lexeme == "init" {
+                    Lox::error_on_token(&declaration.name, "A trait can't have an initializer.");
+                }
+                self.resolve_function(declaration, FunctionType::Method);
+            }
+        }
+        self.end_scope();
+        self.end_scope();
+
+        self.current_class = enclosing_class;
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        _annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output {
         self.declare(name);
         if let Some(initializer_expr) = initializer {
             self.resolve_expr(initializer_expr);
@@ -231,7 +477,17 @@ impl StmtVisitor for Resolver {
         self.define(name);
     }
 
-    fn visit_while(&mut self, condition: &Expr, body: &Stmt) -> Self::Output {
+    fn visit_var_tuple(&mut self, names: &[Token], initializer: &Expr) -> Self::Output {
+        for name in names {
+            self.declare(name);
+        }
+        self.resolve_expr(initializer);
+        for name in names {
+            self.define(name);
+        }
+    }
+
+    fn visit_while(&mut self, _keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output {
         self.resolve_expr(condition);
         self.resolve_stmt(body);
     }
@@ -240,10 +496,24 @@ impl StmtVisitor for Resolver {
 impl ExprVisitor for Resolver {
     type Output = ();
 
-    fn visit_assign(&mut self, name: &Token, value: &Expr) -> Self::Output {
-        let expr = Expr::new_assign(name.clone(), value.clone());
+    fn visit_assign(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        _operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
         self.resolve_expr(value);
-        self.resolve_local(&expr, name);
+        self.check_assignable(name);
+        self.resolve_local(id, name);
+    }
+
+    fn visit_assign_tuple(&mut self, targets: &[(ExprId, Token)], value: &Expr) -> Self::Output {
+        self.resolve_expr(value);
+        for (id, name) in targets {
+            self.check_assignable(name);
+            self.resolve_local(*id, name);
+        }
     }
 
     fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Self::Output {
@@ -251,11 +521,33 @@ impl ExprVisitor for Resolver {
         self.resolve_expr(right);
     }
 
-    fn visit_call(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> Self::Output {
+    fn visit_call(
+        &mut self,
+        _id: ExprId,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output {
         self.resolve_expr(callee);
         for argument in arguments {
             self.resolve_expr(argument);
         }
+        for (_, argument) in keywords {
+            self.resolve_expr(argument);
+        }
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        _question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        self.resolve_expr(condition);
+        self.resolve_expr(then_branch);
+        self.resolve_expr(else_branch);
     }
 
     fn visit_get(&mut self, object: &Expr, _name: &Token) -> Self::Output {
@@ -266,46 +558,72 @@ impl ExprVisitor for Resolver {
         self.resolve_expr(expression);
     }
 
-    fn visit_literal(&mut self, _value: &TokenLiteral) -> Self::Output {}
+    fn visit_literal(&mut self, _token: Option<&Token>, _value: &TokenLiteral) -> Self::Output {}
 
     fn visit_logical(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Self::Output {
         self.resolve_expr(left);
         self.resolve_expr(right);
     }
 
-    fn visit_set(&mut self, object: &Expr, _name: &Token, value: &Expr) -> Self::Output {
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        _name: &Token,
+        _operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
         self.resolve_expr(value);
         self.resolve_expr(object);
     }
 
-    fn visit_super(&mut self, keyword: &Token, method: &Token) -> Self::Output {
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, _method: &Token) -> Self::Output {
         if self.current_class == ClassType::None {
             Lox::error_on_token(keyword, "Can't use 'super' outside of a class.");
-        } else if self.current_class != ClassType::Subclass {
+        } else if self.current_class == ClassType::Class {
             Lox::error_on_token(keyword, "Can't use 'super' in a class with no superclass.");
         }
-        let expr = Expr::new_super(keyword.clone(), method.clone());
-        self.resolve_local(&expr, keyword);
+        self.resolve_local(id, keyword);
     }
 
-    fn visit_this(&mut self, keyword: &Token) -> Self::Output {
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> Self::Output {
         if let ClassType::None = self.current_class {
             Lox::error_on_token(keyword, "Can't use 'this' outside of a class.");
             return;
         }
-        let expr = Expr::new_this(keyword.clone());
-        self.resolve_local(&expr, keyword);
+        self.resolve_local(id, keyword);
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output {
+        for element in elements {
+            self.resolve_expr(element);
+        }
     }
 
     fn visit_unary(&mut self, _operator: &Token, right: &Expr) -> Self::Output {
         self.resolve_expr(right);
     }
 
-    fn visit_variable(&mut self, name: &Token) -> Self::Output {
-        if !self.scopes.is_empty() && self.scopes.last().unwrap().get(&name.lexeme) == Some(&false)
-        {
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> Self::Output {
+        let local = self.scopes.last().and_then(|scope| scope.get(&name.lexeme));
+        if local.is_some_and(|local| !local.defined) {
             Lox::error_on_token(name, "Can't read local variable in its own initializer.");
         }
-        self.resolve_local(&Expr::new_variable(name.clone()), name);
+        self.resolve_local(id, name);
+    }
+
+    fn visit_yield(&mut self, keyword: &Token, value: Option<&Expr>) -> Self::Output {
+        if self.current_function == FunctionType::None {
+            Lox::error_on_token(keyword, "Can't yield from top-level code.");
+        } else if self.current_function == FunctionType::Initializer {
+            Lox::error_on_token(keyword, "Can't yield from an initializer.");
+        } else if !mem::take(&mut self.yield_allowed) {
+            Lox::error_on_token(
+                keyword,
+                "Can only yield at the start of a statement, as in 'yield x;', 'var y = yield x;' or 'y = yield x;'.",
+            );
+        }
+        if let Some(value) = value {
+            self.resolve_expr(value);
+        }
     }
 }
@@ -1,6 +1,10 @@
 use lazy_static::lazy_static;
+use num_bigint_dig::BigInt;
 use ordered_float::OrderedFloat;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
     lox::Lox,
@@ -13,20 +17,26 @@ lazy_static! {
         let mut keywords = HashMap::new();
         keywords.insert("and".into(), And);
         keywords.insert("class".into(), Class);
+        keywords.insert("const".into(), Const);
         keywords.insert("else".into(), Else);
         keywords.insert("false".into(), False);
         keywords.insert("fun".into(), Fun);
         keywords.insert("for".into(), For);
         keywords.insert("if".into(), If);
+        keywords.insert("in".into(), In);
+        keywords.insert("match".into(), Match);
         keywords.insert("nil".into(), Nil);
         keywords.insert("or".into(), Or);
         keywords.insert("print".into(), Print);
         keywords.insert("return".into(), Return);
         keywords.insert("super".into(), Super);
         keywords.insert("this".into(), This);
+        keywords.insert("trait".into(), Trait);
         keywords.insert("true".into(), True);
         keywords.insert("var".into(), Var);
         keywords.insert("while".into(), While);
+        keywords.insert("with".into(), With);
+        keywords.insert("yield".into(), Yield);
         keywords
     };
 }
@@ -37,6 +47,8 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    /// String literals seen so far, so identical literals share one allocation.
+    strings: HashSet<Rc<str>>,
 }
 
 impl Scanner {
@@ -47,6 +59,7 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            strings: HashSet::new(),
         }
     }
 
@@ -76,11 +89,67 @@ impl Scanner {
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
             ',' => self.add_token(Comma),
-            '.' => self.add_token(Dot),
-            '-' => self.add_token(Minus),
-            '+' => self.add_token(Plus),
+            '.' => {
+                if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    self.add_token(DotDotDot);
+                } else {
+                    self.add_token(Dot);
+                }
+            }
             ';' => self.add_token(Semicolon),
-            '*' => self.add_token(Star),
+            '&' => self.add_token(Ampersand),
+            '|' => self.add_token(Pipe),
+            '^' => self.add_token(Caret),
+            '?' => self.add_token(Question),
+            ':' => self.add_token(Colon),
+            '-' => {
+                let token = if self.try_match('=') {
+                    MinusEqual
+                } else if self.try_match('-') {
+                    MinusMinus
+                } else {
+                    Minus
+                };
+                self.add_token(token);
+            }
+            '+' => {
+                let token = if self.try_match('=') {
+                    PlusEqual
+                } else if self.try_match('+') {
+                    PlusPlus
+                } else {
+                    Plus
+                };
+                self.add_token(token);
+            }
+            '%' => {
+                let token = if self.try_match('=') {
+                    PercentEqual
+                } else {
+                    Percent
+                };
+                self.add_token(token);
+            }
+            '*' => {
+                let token = if self.try_match('=') {
+                    StarEqual
+                } else if self.try_match('*') {
+                    StarStar
+                } else {
+                    Star
+                };
+                self.add_token(token);
+            }
+            '~' => {
+                let token = if self.try_match('/') {
+                    TildeSlash
+                } else {
+                    Tilde
+                };
+                self.add_token(token);
+            }
             '!' => {
                 let token = if self.try_match('=') { BangEqual } else { Bang };
                 self.add_token(token);
@@ -88,18 +157,28 @@ impl Scanner {
             '=' => {
                 let token = if self.try_match('=') {
                     EqualEqual
+                } else if self.try_match('>') {
+                    EqualGreater
                 } else {
                     Equal
                 };
                 self.add_token(token);
             }
             '<' => {
-                let token = if self.try_match('=') { LessEqual } else { Less };
+                let token = if self.try_match('=') {
+                    LessEqual
+                } else if self.try_match('<') {
+                    LessLess
+                } else {
+                    Less
+                };
                 self.add_token(token);
             }
             '>' => {
                 let token = if self.try_match('=') {
                     GreaterEqual
+                } else if self.try_match('>') {
+                    GreaterGreater
                 } else {
                     Greater
                 };
@@ -118,6 +197,8 @@ impl Scanner {
                     // consume closing `*/`
                     self.advance();
                     self.advance();
+                } else if self.try_match('=') {
+                    self.add_token(SlashEqual);
                 } else {
                     self.add_token(Slash);
                 }
@@ -129,7 +210,7 @@ impl Scanner {
             _ => {
                 if c.is_digit(10) {
                     self.scan_number();
-                } else if c.is_alphabetic() {
+                } else if c.is_alphabetic() || c == '_' {
                     self.scan_identifier();
                 } else {
                     Lox::error_on_line(self.line, &format!("Unexpected character: {}", c));
@@ -157,18 +238,32 @@ impl Scanner {
         let value: String = self.source[self.start + 1..self.current - 1]
             .iter()
             .collect();
+        let value = self.intern(value);
         self.add_token_literal(TokenType::String, &TokenLiteral::String(value));
     }
 
+    fn intern(&mut self, value: String) -> Rc<str> {
+        if let Some(interned) = self.strings.get(value.as_str()) {
+            return interned.clone();
+        }
+        let interned: Rc<str> = value.into();
+        self.strings.insert(interned.clone());
+        interned
+    }
+
+    /// Scans a number literal, which is an integer unless it has a fractional part. An `n`
+    /// suffix makes an integer literal a big integer.
     fn scan_number(&mut self) {
         while self.peek().is_digit(10) {
             self.advance();
         }
 
         // look for a fractional part.
+        let mut is_float = false;
         if self.peek() == '.' && self.peek_next().is_digit(10) {
             // consume the '.'
             self.advance();
+            is_float = true;
         }
 
         while self.peek().is_digit(10) {
@@ -176,12 +271,33 @@ impl Scanner {
         }
 
         let value_str: String = self.source[self.start..self.current].iter().collect();
-        let value = OrderedFloat::from(value_str.parse::<f64>().unwrap());
-        self.add_token_literal(TokenType::Number, &TokenLiteral::Number(value))
+        if !is_float && self.peek() == 'n' && !self.peek_next().is_alphanumeric() {
+            // consume the 'n'
+            self.advance();
+            let value = value_str.parse::<BigInt>().unwrap();
+            self.add_token_literal(TokenType::Number, &TokenLiteral::BigInt(Rc::new(value)));
+            return;
+        }
+
+        let value = if is_float {
+            TokenLiteral::Number(OrderedFloat::from(value_str.parse::<f64>().unwrap()))
+        } else {
+            match value_str.parse::<i64>() {
+                Ok(value) => TokenLiteral::Integer(value),
+                Err(_) => {
+                    Lox::error_on_line(
+                        self.line,
+                        "Integer literal is too large, add an 'n' suffix for a big integer.",
+                    );
+                    TokenLiteral::Nil
+                }
+            }
+        };
+        self.add_token_literal(TokenType::Number, &value)
     }
 
     fn scan_identifier(&mut self) {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
 
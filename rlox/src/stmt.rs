@@ -1,42 +1,113 @@
 use std::rc::Rc;
 
-use crate::{expr::Expr, token::Token};
+use crate::{expr::Expr, pattern::Pattern, token::Token};
+
+/// A function or method declaration, shared by every `LoxFunction` created from it.
+#[derive(PartialEq)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Param>,
+    /// The `...rest` parameter, which collects any further positional arguments into a
+    /// tuple.
+    pub rest: Option<Token>,
+    /// The type written after the parameter list, as in `fun f(): Number`.
+    pub return_type: Option<TypeAnnotation>,
+    pub body: Vec<Option<Stmt>>,
+    /// Whether the body yields, so calling the function creates a generator.
+    pub is_generator: bool,
+}
+
+/// A parameter of a function, with the expression giving its value when a call leaves it
+/// out. Defaults are evaluated at call time, in the scope of the parameters before them.
+#[derive(Clone, PartialEq)]
+pub struct Param {
+    pub name: Token,
+    pub annotation: Option<TypeAnnotation>,
+    pub default: Option<Expr>,
+}
+
+/// A type written in the source, as in `var x: Number`. Only the `TypeChecker` looks at
+/// annotations; they don't change how a program runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAnnotation {
+    pub name: Token,
+}
+
+/// One arm of a `match`: `pattern if guard => body`.
+#[derive(Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Stmt,
+}
 
 #[derive(Clone, PartialEq)]
 pub enum Stmt {
     Block {
         statements: Vec<Option<Stmt>>,
     },
+    /// A class declaration. `traits` are the variables named after `with`, whose methods
+    /// are mixed into the class.
     Class {
         name: Token,
         superclass: Option<Expr>,
+        traits: Vec<Expr>,
         methods: Vec<Stmt>,
     },
+    /// `const name = initializer;`, a variable that can't be assigned after it is defined.
+    Const {
+        name: Token,
+        annotation: Option<TypeAnnotation>,
+        initializer: Rc<Expr>,
+    },
     Expression {
         expression: Rc<Expr>,
     },
-    Function {
+    ForIn {
+        keyword: Token,
         name: Token,
-        params: Vec<Token>,
-        body: Vec<Option<Stmt>>,
+        iterable: Rc<Expr>,
+        body: Box<Stmt>,
+    },
+    Function {
+        declaration: Rc<FunctionDecl>,
     },
     If {
+        keyword: Token,
         condition: Rc<Expr>,
         then_branch: Box<Stmt>,
         else_branch: Box<Option<Stmt>>,
     },
+    Match {
+        keyword: Token,
+        subject: Rc<Expr>,
+        arms: Vec<MatchArm>,
+    },
     Print {
+        keyword: Token,
         expression: Rc<Expr>,
     },
     Return {
         keyword: Token,
         value: Option<Rc<Expr>>,
     },
+    /// `trait Name { ... }`, methods for classes to mix in.
+    Trait {
+        name: Token,
+        methods: Vec<Stmt>,
+    },
     Var {
         name: Token,
+        annotation: Option<TypeAnnotation>,
         initializer: Rc<Option<Expr>>,
     },
+    /// `var (a, b) = initializer;`, which defines a variable per element of a tuple.
+    VarTuple {
+        names: Vec<Token>,
+        initializer: Rc<Expr>,
+    },
     While {
+        keyword: Token,
         condition: Rc<Expr>,
         body: Box<Stmt>,
     },
@@ -47,34 +118,88 @@ impl Stmt {
         Self::Block { statements }
     }
 
-    pub fn new_class(name: Token, superclass: Option<Expr>, methods: Vec<Stmt>) -> Self {
+    pub fn new_class(
+        name: Token,
+        superclass: Option<Expr>,
+        traits: Vec<Expr>,
+        methods: Vec<Stmt>,
+    ) -> Self {
         Self::Class {
             name,
             superclass,
+            traits,
             methods,
         }
     }
 
+    pub fn new_const(name: Token, annotation: Option<TypeAnnotation>, initializer: Expr) -> Self {
+        Self::Const {
+            name,
+            annotation,
+            initializer: Rc::new(initializer),
+        }
+    }
+
     pub fn new_expression(expression: Expr) -> Self {
         Self::Expression {
             expression: Rc::new(expression),
         }
     }
 
-    pub fn new_function(name: Token, params: Vec<Token>, body: Vec<Option<Stmt>>) -> Self {
-        Self::Function { name, params, body }
+    pub fn new_for_in(keyword: Token, name: Token, iterable: Expr, body: Stmt) -> Self {
+        Self::ForIn {
+            keyword,
+            name,
+            iterable: Rc::new(iterable),
+            body: Box::new(body),
+        }
     }
 
-    pub fn new_if(condition: Expr, then_branch: Stmt, else_branch: Option<Stmt>) -> Self {
+    pub fn new_function(
+        name: Token,
+        params: Vec<Param>,
+        rest: Option<Token>,
+        return_type: Option<TypeAnnotation>,
+        body: Vec<Option<Stmt>>,
+        is_generator: bool,
+    ) -> Self {
+        Self::Function {
+            declaration: Rc::new(FunctionDecl {
+                name,
+                params,
+                rest,
+                return_type,
+                body,
+                is_generator,
+            }),
+        }
+    }
+
+    pub fn new_if(
+        keyword: Token,
+        condition: Expr,
+        then_branch: Stmt,
+        else_branch: Option<Stmt>,
+    ) -> Self {
         Self::If {
+            keyword,
             condition: Rc::new(condition),
             then_branch: Box::new(then_branch),
             else_branch: Box::new(else_branch),
         }
     }
 
-    pub fn new_print(expression: Expr) -> Self {
+    pub fn new_match(keyword: Token, subject: Expr, arms: Vec<MatchArm>) -> Self {
+        Self::Match {
+            keyword,
+            subject: Rc::new(subject),
+            arms,
+        }
+    }
+
+    pub fn new_print(keyword: Token, expression: Expr) -> Self {
         Self::Print {
+            keyword,
             expression: Rc::new(expression),
         }
     }
@@ -93,14 +218,32 @@ impl Stmt {
         }
     }
 
-    pub fn new_var(name: Token, initializer: Option<Expr>) -> Self {
+    pub fn new_trait(name: Token, methods: Vec<Stmt>) -> Self {
+        Self::Trait { name, methods }
+    }
+
+    pub fn new_var(
+        name: Token,
+        annotation: Option<TypeAnnotation>,
+        initializer: Option<Expr>,
+    ) -> Self {
         Self::Var {
             name,
+            annotation,
+            initializer: Rc::new(initializer),
+        }
+    }
+
+    pub fn new_var_tuple(names: Vec<Token>, initializer: Expr) -> Self {
+        Self::VarTuple {
+            names,
             initializer: Rc::new(initializer),
         }
     }
-    pub fn new_while(condition: Expr, body: Stmt) -> Self {
+
+    pub fn new_while(keyword: Token, condition: Expr, body: Stmt) -> Self {
         Self::While {
+            keyword,
             condition: Rc::new(condition),
             body: Box::new(body),
         }
@@ -112,19 +255,88 @@ impl Stmt {
             Self::Class {
                 name,
                 superclass,
+                traits,
                 methods,
-            } => visitor.visit_class(name, superclass, methods),
+            } => visitor.visit_class(name, superclass, traits, methods),
+            Self::Const {
+                name,
+                annotation,
+                initializer,
+            } => visitor.visit_const(name, annotation.as_ref(), initializer),
             Self::Expression { expression } => visitor.visit_expression(expression),
-            Self::Function { name, params, body } => visitor.visit_function(name, params, body),
+            Self::ForIn {
+                keyword,
+                name,
+                iterable,
+                body,
+            } => visitor.visit_for_in(keyword, name, iterable, body),
+            Self::Function { declaration } => visitor.visit_function(declaration),
             Self::If {
+                keyword,
                 condition,
                 then_branch,
                 else_branch,
-            } => visitor.visit_if(condition, then_branch, else_branch),
-            Self::Print { expression } => visitor.visit_print(expression),
+            } => visitor.visit_if(keyword, condition, then_branch, else_branch),
+            Self::Match {
+                keyword,
+                subject,
+                arms,
+            } => visitor.visit_match(keyword, subject, arms),
+            Self::Print {
+                keyword,
+                expression,
+            } => visitor.visit_print(keyword, expression),
             Self::Return { keyword, value } => visitor.visit_return(keyword, value),
-            Self::Var { name, initializer } => visitor.visit_var(name, initializer),
-            Self::While { condition, body } => visitor.visit_while(condition, body),
+            Self::Trait { name, methods } => visitor.visit_trait(name, methods),
+            Self::Var {
+                name,
+                annotation,
+                initializer,
+            } => visitor.visit_var(name, annotation.as_ref(), initializer),
+            Self::VarTuple { names, initializer } => visitor.visit_var_tuple(names, initializer),
+            Self::While {
+                keyword,
+                condition,
+                body,
+            } => visitor.visit_while(keyword, condition, body),
+        }
+    }
+
+    /// The `yield` this statement evaluates before anything else, if any. These are the
+    /// only places a generator can suspend: `yield x;`, `var y = yield x;` and `y = yield x;`,
+    /// including compound and property assignments.
+    pub fn first_yield(&self) -> Option<&Expr> {
+        let expression = match self {
+            Self::Expression { expression } => match expression.as_ref() {
+                Expr::Assign { value, .. }
+                | Expr::AssignTuple { value, .. }
+                | Expr::Set { value, .. } => value.as_ref(),
+                expression => expression,
+            },
+            Self::Var { initializer, .. } => initializer.as_ref().as_ref()?,
+            Self::Const { initializer, .. } | Self::VarTuple { initializer, .. } => initializer,
+            _ => return None,
+        };
+        matches!(expression, Expr::Yield { .. }).then_some(expression)
+    }
+
+    /// Source line the statement starts on, if it has one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::Block { .. } => None,
+            Self::Class { name, .. }
+            | Self::Const { name, .. }
+            | Self::Trait { name, .. }
+            | Self::Var { name, .. } => Some(name.line),
+            Self::VarTuple { names, .. } => Some(names[0].line),
+            Self::Function { declaration } => Some(declaration.name.line),
+            Self::Expression { expression } => expression.line(),
+            Self::ForIn { keyword, .. }
+            | Self::If { keyword, .. }
+            | Self::Match { keyword, .. }
+            | Self::Print { keyword, .. }
+            | Self::Return { keyword, .. }
+            | Self::While { keyword, .. } => Some(keyword.line),
         }
     }
 }
@@ -136,24 +348,42 @@ pub trait StmtVisitor {
     fn visit_class(
         &mut self,
         name: &Token,
-        class: &Option<Expr>,
+        superclass: &Option<Expr>,
+        traits: &[Expr],
         methods: &Vec<Stmt>,
     ) -> Self::Output;
+    fn visit_const(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output;
     fn visit_expression(&mut self, expression: &Expr) -> Self::Output;
-    fn visit_function(
+    fn visit_for_in(
         &mut self,
+        keyword: &Token,
         name: &Token,
-        params: &[Token],
-        body: &[Option<Stmt>],
+        iterable: &Expr,
+        body: &Stmt,
     ) -> Self::Output;
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output;
     fn visit_if(
         &mut self,
+        keyword: &Token,
         condition: &Expr,
         then_branch: &Stmt,
         else_branch: &Option<Stmt>,
     ) -> Self::Output;
-    fn visit_print(&mut self, expression: &Expr) -> Self::Output;
+    fn visit_match(&mut self, keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output;
+    fn visit_print(&mut self, keyword: &Token, expression: &Expr) -> Self::Output;
     fn visit_return(&mut self, keyword: &Token, value: &Option<Rc<Expr>>) -> Self::Output;
-    fn visit_var(&mut self, name: &Token, initializer: &Option<Expr>) -> Self::Output;
-    fn visit_while(&mut self, condition: &Expr, body: &Stmt) -> Self::Output;
+    fn visit_trait(&mut self, name: &Token, methods: &[Stmt]) -> Self::Output;
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output;
+    fn visit_var_tuple(&mut self, names: &[Token], initializer: &Expr) -> Self::Output;
+    fn visit_while(&mut self, keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output;
 }
@@ -0,0 +1,344 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Exit code of a script with a compile error.
+const EXIT_COMPILE_ERROR: i32 = 65;
+/// Exit code of a script that hit a runtime error.
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+/// What a test script declares it will do, read from annotations in its comments.
+///
+/// The annotations follow the layout of the Crafting Interpreters test suite:
+///
+/// - `// expect: value` expects `value` as the next line of stdout.
+/// - `// expect runtime error: msg` expects the script to fail on this line with `msg`.
+/// - `// Error at 'x': msg` expects a compile error on this line.
+/// - `// Warning at 'x': msg` expects a warning on this line, which doesn't stop the script.
+/// - `// [line N] Error at 'x': msg` expects a compile error on line `N`. The `[java line N]`
+///   form is accepted too, while `[c line N]` errors only apply to clox and are ignored.
+/// - `// options: --flag` runs the script with the given command line options.
+#[derive(Debug, Default)]
+struct Expectations {
+    options: Vec<String>,
+    output: Vec<String>,
+    /// Compile errors and warnings, in the order they are reported.
+    errors: Vec<String>,
+    runtime_error: Option<String>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Self::default();
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let comment = match line.find("//") {
+                Some(start) => line[start + 2..].trim(),
+                None => continue,
+            };
+
+            if let Some(options) = comment.strip_prefix("options:") {
+                expectations
+                    .options
+                    .extend(options.split_whitespace().map(String::from));
+            } else if let Some(value) = comment.strip_prefix("expect:") {
+                expectations.output.push(value.trim().into());
+            } else if let Some(msg) = comment.strip_prefix("expect runtime error:") {
+                expectations.runtime_error =
+                    Some(format!("[line {}] Error: {}", line_number, msg.trim()));
+            } else if comment.starts_with("Error") || comment.starts_with("Warning") {
+                expectations
+                    .errors
+                    .push(format!("[line {}] {}", line_number, comment));
+            } else if let Some(rest) = comment
+                .strip_prefix("[line ")
+                .or_else(|| comment.strip_prefix("[java line "))
+            {
+                if let Some((number, error)) = rest.split_once(']') {
+                    expectations
+                        .errors
+                        .push(format!("[line {}] {}", number.trim(), error.trim()));
+                }
+            }
+        }
+        expectations
+    }
+
+    fn is_empty(&self) -> bool {
+        self.output.is_empty() && self.errors.is_empty() && self.runtime_error.is_none()
+    }
+
+    /// Whether a compile error is expected, rather than only warnings.
+    fn has_compile_error(&self) -> bool {
+        self.errors.iter().any(|error| !error.contains("] Warning"))
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.has_compile_error() {
+            EXIT_COMPILE_ERROR
+        } else if self.runtime_error.is_some() {
+            EXIT_RUNTIME_ERROR
+        } else {
+            0
+        }
+    }
+}
+
+/// Runs annotated `.lox` scripts and checks their output against the annotations.
+///
+/// Each script runs in a separate `rlox` process, so crashes and exit codes are observed
+/// exactly as a user would see them. Scripts without annotations, such as benchmarks, are
+/// skipped.
+pub struct TestRunner {
+    interpreter: PathBuf,
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl TestRunner {
+    /// Runs scripts with the current executable, for `rlox test`.
+    pub fn new() -> Self {
+        Self::with_interpreter(env::current_exe().expect("Could not locate the rlox executable."))
+    }
+
+    /// Runs scripts with the `rlox` executable at `interpreter`.
+    pub fn with_interpreter(interpreter: impl Into<PathBuf>) -> Self {
+        Self {
+            interpreter: interpreter.into(),
+            passed: 0,
+            failed: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Runs every script under `dir` and returns whether they all passed.
+    pub fn run(&mut self, dir: &str) -> bool {
+        let mut paths = Vec::new();
+        collect_scripts(Path::new(dir), &mut paths);
+        paths.sort();
+
+        for path in &paths {
+            self.run_script(path);
+        }
+
+        println!(
+            "{} passed, {} failed, {} skipped.",
+            self.passed, self.failed, self.skipped
+        );
+        self.failed == 0
+    }
+
+    fn run_script(&mut self, path: &Path) {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                println!("FAIL {}\n  could not read script: {}", path.display(), e);
+                self.failed += 1;
+                return;
+            }
+        };
+        let expectations = Expectations::parse(&source);
+        if expectations.is_empty() {
+            println!("SKIP {}", path.display());
+            self.skipped += 1;
+            return;
+        }
+
+        let output = match Command::new(&self.interpreter)
+            .args(&expectations.options)
+            .arg(path)
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                println!("FAIL {}\n  could not run script: {}", path.display(), e);
+                self.failed += 1;
+                return;
+            }
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout: Vec<&str> = stdout.lines().collect();
+        let stderr: Vec<&str> = stderr.lines().collect();
+
+        let mut failures = Vec::new();
+        if stdout != expectations.output {
+            failures.push(format!("stdout:\n{}", diff(&expectations.output, &stdout)));
+        }
+
+        let mut expected_stderr = expectations.errors.clone();
+        if let (Some(error), false) = (
+            &expectations.runtime_error,
+            expectations.has_compile_error(),
+        ) {
+            expected_stderr.push(error.clone());
+        }
+        if stderr != expected_stderr {
+            failures.push(format!("stderr:\n{}", diff(&expected_stderr, &stderr)));
+        }
+
+        let expected_code = expectations.exit_code();
+        match output.status.code() {
+            Some(code) if code == expected_code => {}
+            Some(code) => failures.push(format!(
+                "exit code: expected {}, got {}",
+                expected_code, code
+            )),
+            None => failures.push("exit code: killed by a signal".into()),
+        }
+
+        if failures.is_empty() {
+            println!("PASS {}", path.display());
+            self.passed += 1;
+        } else {
+            println!("FAIL {}", path.display());
+            for failure in failures {
+                for line in failure.lines() {
+                    println!("  {}", line);
+                }
+            }
+            self.failed += 1;
+        }
+    }
+}
+
+impl Default for TestRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn collect_scripts(path: &Path, paths: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        if path.extension().is_some_and(|ext| ext == "lox") {
+            paths.push(path.to_path_buf());
+        }
+        return;
+    }
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            collect_scripts(&entry.path(), paths);
+        }
+    }
+}
+
+/// Line diff of `expected` against `actual`, marking missing lines with `-` and unexpected
+/// ones with `+`.
+fn diff<E: AsRef<str>, A: AsRef<str>>(expected: &[E], actual: &[A]) -> String {
+    // longest common subsequence table, filled from the end
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i].as_ref() == actual[j].as_ref() {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i].as_ref() == actual[j].as_ref() {
+            out.push_str(&format!("  {}\n", expected[i].as_ref()));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("- {}\n", expected[i].as_ref()));
+            i += 1;
+        } else {
+            out.push_str(&format!("+ {}\n", actual[j].as_ref()));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let expectations = Expectations::parse(
+            "print 1; // expect: 1
+print \"a b\";   //   expect:   a b
+// options: -O --strict
+",
+        );
+        assert_eq!(expectations.output, vec!["1", "a b"]);
+        assert_eq!(expectations.options, vec!["-O", "--strict"]);
+        assert!(expectations.errors.is_empty());
+        assert_eq!(expectations.runtime_error, None);
+        assert_eq!(expectations.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_parse_runtime_error() {
+        let expectations = Expectations::parse(
+            "print 1; // expect: 1
+nil(); // expect runtime error: Can only call functions and classes.
+",
+        );
+        assert_eq!(expectations.output, vec!["1"]);
+        assert_eq!(
+            expectations.runtime_error.as_deref(),
+            Some("[line 2] Error: Can only call functions and classes.")
+        );
+        assert_eq!(expectations.exit_code(), EXIT_RUNTIME_ERROR);
+    }
+
+    #[test]
+    fn test_parse_compile_errors() {
+        let expectations = Expectations::parse(
+            "return 1; // Error at 'return': Can't return from top-level code.
+// [line 5] Error at end: Expect ';' after value.
+// [java line 6] Error at 'x': Expect expression.
+// [c line 7] Error at 'x': Only clox reports this.
+",
+        );
+        assert_eq!(
+            expectations.errors,
+            vec![
+                "[line 1] Error at 'return': Can't return from top-level code.",
+                "[line 5] Error at end: Expect ';' after value.",
+                "[line 6] Error at 'x': Expect expression.",
+            ]
+        );
+        assert_eq!(expectations.exit_code(), EXIT_COMPILE_ERROR);
+    }
+
+    #[test]
+    fn test_warnings_are_not_compile_errors() {
+        let expectations =
+            Expectations::parse("var a; // Warning at 'a': Local variable is never used.\n");
+        assert!(!expectations.has_compile_error());
+        assert_eq!(expectations.exit_code(), 0);
+
+        // a compile error stops the script before any runtime error
+        let expectations = Expectations::parse(
+            "nil(); // expect runtime error: Can only call functions and classes.
+return; // Error at 'return': Can't return from top-level code.
+",
+        );
+        assert_eq!(expectations.exit_code(), EXIT_COMPILE_ERROR);
+    }
+
+    #[test]
+    fn test_unannotated_script_is_empty() {
+        assert!(Expectations::parse("// a benchmark\nprint clock();\n").is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(&["a", "b"], &["a", "b"]), "  a\n  b\n");
+        assert_eq!(diff(&["a", "b", "c"], &["a", "c"]), "  a\n- b\n  c\n");
+        assert_eq!(diff(&["a", "c"], &["a", "b", "c"]), "  a\n+ b\n  c\n");
+        assert_eq!(diff(&["1"], &["2"]), "- 1\n+ 2\n");
+        assert_eq!(diff::<&str, &str>(&[], &["x"]), "+ x\n");
+    }
+}
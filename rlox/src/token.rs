@@ -1,6 +1,7 @@
 use core::fmt;
-use std::hash::Hash;
+use std::{hash::Hash, rc::Rc};
 
+use num_bigint_dig::BigInt;
 use ordered_float::OrderedFloat;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -45,21 +46,41 @@ pub enum TokenType {
     RightBrace,
     Comma,
     Dot,
-    Minus,
-    Plus,
     Semicolon,
-    Slash,
-    Star,
+    Ampersand,
+    Pipe,
+    Caret,
+    Question,
+    Colon,
 
     // one or two character tokens.
     Bang,
     BangEqual,
+    DotDotDot,
     Equal,
     EqualEqual,
+    EqualGreater,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
+    Minus,
+    MinusEqual,
+    MinusMinus,
+    Percent,
+    PercentEqual,
+    Plus,
+    PlusEqual,
+    PlusPlus,
+    Slash,
+    SlashEqual,
+    Star,
+    StarEqual,
+    StarStar,
+    Tilde,
+    TildeSlash,
 
     // Literals.
     Identifier,
@@ -69,20 +90,26 @@ pub enum TokenType {
     // Keywords.
     And,
     Class,
+    Const,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Trait,
     True,
     Var,
     While,
+    With,
+    Yield,
 
     // end of file.
     EOF,
@@ -97,8 +124,10 @@ impl fmt::Display for TokenType {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TokenLiteral {
     Nil,
-    String(String),
+    String(Rc<str>),
     Number(OrderedFloat<f64>),
+    Integer(i64),
+    BigInt(Rc<BigInt>),
     Bool(bool),
 }
 
@@ -108,6 +137,8 @@ impl fmt::Display for TokenLiteral {
             TokenLiteral::Nil => f.write_str("nil"),
             TokenLiteral::String(s) => f.write_str(s),
             TokenLiteral::Number(n) => write!(f, "{}", n),
+            TokenLiteral::Integer(n) => write!(f, "{}", n),
+            TokenLiteral::BigInt(n) => write!(f, "{}", n),
             TokenLiteral::Bool(b) => write!(f, "{}", b),
         }
     }
@@ -0,0 +1,740 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    expr::{Expr, ExprId, ExprVisitor},
+    lox::Lox,
+    stmt::{FunctionDecl, MatchArm, Stmt, StmtVisitor, TypeAnnotation},
+    token::{Token, TokenLiteral, TokenType},
+};
+
+/// The type of a value, as far as the `TypeChecker` can tell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// A value the checker knows nothing about, which is compatible with every type.
+    Any,
+    Nil,
+    Bool,
+    Number,
+    String,
+    Tuple,
+    /// A function, with its parameter and return types if it is declared in the program.
+    Function(Option<Rc<Signature>>),
+    /// The class called `name` itself, which creates instances when called.
+    Class(Rc<str>),
+    /// An instance of the class called `name`, or of one of its subclasses.
+    Instance(Rc<str>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => f.write_str("Any"),
+            Self::Nil => f.write_str("Nil"),
+            Self::Bool => f.write_str("Bool"),
+            Self::Number => f.write_str("Number"),
+            Self::String => f.write_str("String"),
+            Self::Tuple => f.write_str("Tuple"),
+            Self::Function(_) => f.write_str("Function"),
+            Self::Class(name) => write!(f, "class {}", name),
+            Self::Instance(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Parameter and return types of a declared function. Parameters and return types
+/// without an annotation are `Any`.
+#[derive(Debug, PartialEq)]
+pub struct Signature {
+    params: Vec<(String, Type)>,
+    returns: Type,
+    /// Whether calling the function creates a generator rather than returning `returns`.
+    is_generator: bool,
+}
+
+/// A variable in scope while checking.
+struct Variable {
+    ty: Type,
+    /// Whether `ty` can't change, because the variable was annotated or is a constant.
+    /// Assigning a value of another type to other variables makes them `Any`.
+    fixed: bool,
+}
+
+/// A class or trait, whose name can be used as a type.
+struct ClassInfo {
+    superclass: Option<Rc<str>>,
+    /// Names of the traits a class uses, which its instances can be used as.
+    traits: Vec<Rc<str>>,
+    /// Signature of the class's own `init` method, if it has one.
+    init: Option<Rc<Signature>>,
+}
+
+/// The function whose body is being checked.
+struct FunctionContext {
+    name: String,
+    /// The annotated return type, or `Any` if there is none.
+    returns: Type,
+}
+
+/// Checks type annotations before the program is resolved, when running with
+/// `--typecheck`.
+///
+/// Types of unannotated variables are inferred from their initializers and the values
+/// assigned to them. Anything the checker can't work out is `Any`, so unannotated code
+/// passes unchanged. Mismatches are reported as compile errors with their column.
+pub struct TypeChecker {
+    /// Offset of the first character of each line, to turn a token's cursor into a
+    /// column.
+    line_starts: Vec<usize>,
+    globals: HashMap<String, Variable>,
+    scopes: Vec<HashMap<String, Variable>>,
+    classes: HashMap<Rc<str>, ClassInfo>,
+    function: Option<FunctionContext>,
+    current_class: Option<Rc<str>>,
+}
+
+impl TypeChecker {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (offset, c) in source.chars().enumerate() {
+            if c == '\n' {
+                line_starts.push(offset + 1);
+            }
+        }
+        Self {
+            line_starts,
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            classes: HashMap::new(),
+            function: None,
+            current_class: None,
+        }
+    }
+
+    pub fn check(&mut self, statements: &[Option<Stmt>]) {
+        // classes and traits declared further down can already be used in annotations
+        for statement in statements.iter().flatten() {
+            match statement {
+                Stmt::Class {
+                    name,
+                    superclass,
+                    traits,
+                    ..
+                } => self.declare_class(name, superclass.as_ref(), traits),
+                Stmt::Trait { name, .. } => self.declare_class(name, None, &[]),
+                _ => {}
+            }
+        }
+        for statement in statements.iter().flatten() {
+            self.check_stmt(statement);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        stmt.accept_visitor(self);
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        expr.accept_visitor(self)
+    }
+
+    fn error(&self, token: &Token, message: &str) {
+        let line_start = self.line_starts.get(token.line - 1).copied().unwrap_or(0);
+        let column = token.cursor.saturating_sub(line_start) + 1;
+        Lox::error_at_column(token, column, message);
+    }
+
+    /// Reports `actual` at `token` unless a value of that type can be used where
+    /// `expected` is declared.
+    fn expect(
+        &self,
+        expected: &Type,
+        actual: &Type,
+        token: &Token,
+        message: impl FnOnce() -> String,
+    ) {
+        if !self.accepts(expected, actual) {
+            self.error(token, &message());
+        }
+    }
+
+    fn accepts(&self, expected: &Type, actual: &Type) -> bool {
+        match (expected, actual) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function(_), Type::Function(_) | Type::Class(_)) => true,
+            (Type::Instance(expected), Type::Instance(actual)) => {
+                self.is_subclass(actual, expected)
+            }
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    /// `class` followed by the classes it inherits from, nearest first.
+    fn lineage(&self, class: &Rc<str>) -> Vec<(&Rc<str>, &ClassInfo)> {
+        let mut lineage = Vec::new();
+        let mut class = Some(class);
+        while let Some((name, info)) = class.and_then(|name| self.classes.get_key_value(name)) {
+            // the resolver only rejects cyclic inheritance after this pass
+            if lineage.iter().any(|(seen, _)| *seen == name) {
+                break;
+            }
+            lineage.push((name, info));
+            class = info.superclass.as_ref();
+        }
+        lineage
+    }
+
+    /// Whether `class` is `ancestor`, inherits from it or uses it as a trait.
+    fn is_subclass(&self, class: &Rc<str>, ancestor: &Rc<str>) -> bool {
+        class == ancestor
+            || self
+                .lineage(class)
+                .iter()
+                .any(|(name, info)| *name == ancestor || info.traits.contains(ancestor))
+    }
+
+    /// The signature of the `init` method instances of `class` are created with.
+    fn initializer(&self, class: &Rc<str>) -> Option<Rc<Signature>> {
+        self.lineage(class)
+            .iter()
+            .find_map(|(_, info)| info.init.clone())
+    }
+
+    fn declare_class(&mut self, name: &Token, superclass: Option<&Expr>, traits: &[Expr]) {
+        let class_name = |expr: &Expr| match expr {
+            Expr::Variable { name, .. } => Some(Rc::from(name.lexeme.as_str())),
+            _ => None,
+        };
+        self.classes.insert(
+            name.lexeme.as_str().into(),
+            ClassInfo {
+                superclass: superclass.and_then(class_name),
+                traits: traits.iter().filter_map(class_name).collect(),
+                init: None,
+            },
+        );
+    }
+
+    fn annotated_type(&self, annotation: &TypeAnnotation) -> Type {
+        let name = annotation.name.lexeme.as_str();
+        match name {
+            "Any" => Type::Any,
+            "Nil" => Type::Nil,
+            "Bool" => Type::Bool,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "Tuple" => Type::Tuple,
+            "Function" => Type::Function(None),
+            _ if self.classes.contains_key(name) => Type::Instance(name.into()),
+            _ => {
+                self.error(&annotation.name, &format!("Unknown type '{}'.", name));
+                Type::Any
+            }
+        }
+    }
+
+    fn optional_type(&self, annotation: Option<&TypeAnnotation>) -> Type {
+        annotation.map_or(Type::Any, |annotation| self.annotated_type(annotation))
+    }
+
+    fn declare(&mut self, name: &Token, ty: Type, fixed: bool) {
+        let scope = self.scopes.last_mut().unwrap_or(&mut self.globals);
+        scope.insert(name.lexeme.clone(), Variable { ty, fixed });
+    }
+
+    fn look_up(&self, name: &Token) -> Option<&Variable> {
+        self.scopes
+            .iter()
+            .rev()
+            .chain([&self.globals])
+            .find_map(|scope| scope.get(&name.lexeme))
+    }
+
+    fn assign(&mut self, name: &Token, ty: &Type) {
+        let Some(variable) = self.look_up(name) else {
+            return;
+        };
+        if variable.fixed {
+            let expected = variable.ty.clone();
+            self.expect(&expected, ty, name, || {
+                format!("Expected {} but got {}.", expected, ty)
+            });
+            return;
+        }
+        let scope = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .chain([&mut self.globals])
+            .find(|scope| scope.contains_key(&name.lexeme))
+            .unwrap();
+        let variable = scope.get_mut(&name.lexeme).unwrap();
+        variable.ty = join(&variable.ty, ty);
+    }
+
+    fn signature(&self, declaration: &FunctionDecl) -> Rc<Signature> {
+        let params = declaration
+            .params
+            .iter()
+            .map(|param| {
+                let ty = self.optional_type(param.annotation.as_ref());
+                (param.name.lexeme.clone(), ty)
+            })
+            .collect();
+        let returns = self.optional_type(declaration.return_type.as_ref());
+        Rc::new(Signature {
+            params,
+            returns,
+            is_generator: declaration.is_generator,
+        })
+    }
+
+    fn check_function(&mut self, declaration: &FunctionDecl, signature: &Signature) {
+        self.scopes.push(HashMap::new());
+        for (param, (_, ty)) in declaration.params.iter().zip(&signature.params) {
+            if let Some(default) = &param.default {
+                let actual = self.check_expr(default);
+                self.expect(ty, &actual, &param.name, || {
+                    format!("Expected {} but got {}.", ty, actual)
+                });
+            }
+            self.declare(&param.name, ty.clone(), param.annotation.is_some());
+        }
+        if let Some(rest) = &declaration.rest {
+            self.declare(rest, Type::Tuple, false);
+        }
+
+        let enclosing = self.function.replace(FunctionContext {
+            name: declaration.name.lexeme.clone(),
+            returns: signature.returns.clone(),
+        });
+        for statement in declaration.body.iter().flatten() {
+            self.check_stmt(statement);
+        }
+        self.function = enclosing;
+        self.scopes.pop();
+    }
+
+    fn check_arguments(
+        &self,
+        signature: &Signature,
+        arguments: &[(&Token, Type)],
+        keywords: &[(Token, Type)],
+    ) {
+        for ((name, expected), (token, actual)) in signature.params.iter().zip(arguments) {
+            self.expect(expected, actual, token, || {
+                format!(
+                    "Expected {} for parameter '{}' but got {}.",
+                    expected, name, actual
+                )
+            });
+        }
+        for (keyword, actual) in keywords {
+            let param = signature
+                .params
+                .iter()
+                .find(|(name, _)| *name == keyword.lexeme);
+            if let Some((name, expected)) = param {
+                self.expect(expected, actual, keyword, || {
+                    format!(
+                        "Expected {} for parameter '{}' but got {}.",
+                        expected, name, actual
+                    )
+                });
+            }
+        }
+    }
+}
+
+/// The type of a variable that may hold a value of type `a` or of type `b`.
+fn join(a: &Type, b: &Type) -> Type {
+    if a == b {
+        a.clone()
+    } else {
+        Type::Any
+    }
+}
+
+fn is_number(ty: &Type) -> bool {
+    *ty == Type::Number
+}
+
+/// The type `left operator right` evaluates to. Instances can overload operators, so
+/// their results are unknown.
+fn binary_type(left: &Type, operator: &Token, right: &Type) -> Type {
+    if matches!(left, Type::Any | Type::Instance(_)) || matches!(right, Type::Instance(_)) {
+        return Type::Any;
+    }
+    match operator.ttype {
+        TokenType::BangEqual
+        | TokenType::EqualEqual
+        | TokenType::Greater
+        | TokenType::GreaterEqual
+        | TokenType::Less
+        | TokenType::LessEqual => Type::Bool,
+        TokenType::Plus if *left == Type::String => Type::String,
+        _ if is_number(left) && is_number(right) => Type::Number,
+        _ => Type::Any,
+    }
+}
+
+impl StmtVisitor for TypeChecker {
+    type Output = ();
+
+    fn visit_block(&mut self, statements: &Vec<Option<Stmt>>) -> Self::Output {
+        self.scopes.push(HashMap::new());
+        for statement in statements.iter().flatten() {
+            self.check_stmt(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: &Option<Expr>,
+        traits: &[Expr],
+        methods: &Vec<Stmt>,
+    ) -> Self::Output {
+        if let Some(superclass) = superclass {
+            self.check_expr(superclass);
+        }
+        for used in traits {
+            self.check_expr(used);
+        }
+        if !self.scopes.is_empty() {
+            self.declare_class(name, superclass.as_ref(), traits);
+        }
+        let class: Rc<str> = name.lexeme.as_str().into();
+        self.declare(name, Type::Class(class.clone()), false);
+
+        let enclosing_class = self.current_class.replace(class.clone());
+        for method in methods {
+            if let Stmt::Function { declaration } = method {
+                let signature = self.signature(declaration);
+                if declaration.name.lexeme == "init" {
+                    if let Some(info) = self.classes.get_mut(&class) {
+                        info.init = Some(signature.clone());
+                    }
+                }
+                self.check_function(declaration, &signature);
+            }
+        }
+        self.current_class = enclosing_class;
+    }
+
+    fn visit_const(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Expr,
+    ) -> Self::Output {
+        let actual = self.check_expr(initializer);
+        let ty = match annotation {
+            Some(annotation) => {
+                let expected = self.annotated_type(annotation);
+                self.expect(&expected, &actual, name, || {
+                    format!("Expected {} but got {}.", expected, actual)
+                });
+                expected
+            }
+            None => actual,
+        };
+        self.declare(name, ty, true);
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) -> Self::Output {
+        self.check_expr(expression);
+    }
+
+    fn visit_for_in(
+        &mut self,
+        _keyword: &Token,
+        name: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Self::Output {
+        self.check_expr(iterable);
+        self.scopes.push(HashMap::new());
+        self.declare(name, Type::Any, false);
+        self.check_stmt(body);
+        self.scopes.pop();
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> Self::Output {
+        let signature = self.signature(declaration);
+        self.declare(
+            &declaration.name,
+            Type::Function(Some(signature.clone())),
+            false,
+        );
+        self.check_function(declaration, &signature);
+    }
+
+    fn visit_if(
+        &mut self,
+        _keyword: &Token,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: &Option<Stmt>,
+    ) -> Self::Output {
+        self.check_expr(condition);
+        self.check_stmt(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.check_stmt(else_branch);
+        }
+    }
+
+    fn visit_match(&mut self, _keyword: &Token, subject: &Expr, arms: &[MatchArm]) -> Self::Output {
+        self.check_expr(subject);
+        for arm in arms {
+            self.scopes.push(HashMap::new());
+            for name in arm.pattern.bindings() {
+                self.declare(name, Type::Any, false);
+            }
+            if let Some(guard) = &arm.guard {
+                self.check_expr(guard);
+            }
+            self.check_stmt(&arm.body);
+            self.scopes.pop();
+        }
+    }
+
+    fn visit_print(&mut self, _keyword: &Token, expression: &Expr) -> Self::Output {
+        self.check_expr(expression);
+    }
+
+    fn visit_return(&mut self, keyword: &Token, value: &Option<Rc<Expr>>) -> Self::Output {
+        let actual = match value {
+            Some(value) => self.check_expr(value),
+            None => Type::Nil,
+        };
+        if let Some(function) = &self.function {
+            self.expect(&function.returns, &actual, keyword, || {
+                format!(
+                    "Expected '{}' to return {} but got {}.",
+                    function.name, function.returns, actual
+                )
+            });
+        }
+    }
+
+    fn visit_trait(&mut self, name: &Token, methods: &[Stmt]) -> Self::Output {
+        if !self.scopes.is_empty() {
+            self.declare_class(name, None, &[]);
+        }
+        self.declare(name, Type::Any, false);
+
+        // a trait method's `this` can be an instance of any class using the trait
+        let enclosing_class = self.current_class.take();
+        for method in methods {
+            if let Stmt::Function { declaration } = method {
+                let signature = self.signature(declaration);
+                self.check_function(declaration, &signature);
+            }
+        }
+        self.current_class = enclosing_class;
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &Token,
+        annotation: Option<&TypeAnnotation>,
+        initializer: &Option<Expr>,
+    ) -> Self::Output {
+        let actual = match initializer {
+            Some(initializer) => self.check_expr(initializer),
+            None => Type::Any,
+        };
+        match annotation {
+            Some(annotation) => {
+                let expected = self.annotated_type(annotation);
+                self.expect(&expected, &actual, name, || {
+                    format!("Expected {} but got {}.", expected, actual)
+                });
+                self.declare(name, expected, true);
+            }
+            None => self.declare(name, actual, false),
+        }
+    }
+
+    fn visit_var_tuple(&mut self, names: &[Token], initializer: &Expr) -> Self::Output {
+        self.check_expr(initializer);
+        for name in names {
+            self.declare(name, Type::Any, false);
+        }
+    }
+
+    fn visit_while(&mut self, _keyword: &Token, condition: &Expr, body: &Stmt) -> Self::Output {
+        self.check_expr(condition);
+        self.check_stmt(body);
+    }
+}
+
+impl ExprVisitor for TypeChecker {
+    type Output = Type;
+
+    fn visit_assign(
+        &mut self,
+        _id: ExprId,
+        name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        let mut ty = self.check_expr(value);
+        if let Some(operator) = operator {
+            let current = self
+                .look_up(name)
+                .map_or(Type::Any, |variable| variable.ty.clone());
+            ty = binary_type(&current, operator, &ty);
+        }
+        self.assign(name, &ty);
+        ty
+    }
+
+    fn visit_assign_tuple(&mut self, targets: &[(ExprId, Token)], value: &Expr) -> Self::Output {
+        let ty = self.check_expr(value);
+        for (_, name) in targets {
+            self.assign(name, &Type::Any);
+        }
+        ty
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        let left = self.check_expr(left);
+        let right = self.check_expr(right);
+        binary_type(&left, operator, &right)
+    }
+
+    fn visit_call(
+        &mut self,
+        _id: ExprId,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Self::Output {
+        let callee = self.check_expr(callee);
+        // mismatches are reported where the argument starts, or at `)` if it has no token
+        let arguments: Vec<(&Token, Type)> = arguments
+            .iter()
+            .map(|argument| {
+                let token = argument.token().unwrap_or(paren);
+                (token, self.check_expr(argument))
+            })
+            .collect();
+        let keywords: Vec<(Token, Type)> = keywords
+            .iter()
+            .map(|(name, argument)| (name.clone(), self.check_expr(argument)))
+            .collect();
+
+        let (signature, result) = match callee {
+            Type::Function(Some(signature)) => {
+                let result = if signature.is_generator {
+                    Type::Any
+                } else {
+                    signature.returns.clone()
+                };
+                (Some(signature), result)
+            }
+            Type::Class(class) => (self.initializer(&class), Type::Instance(class)),
+            _ => (None, Type::Any),
+        };
+        if let Some(signature) = signature {
+            self.check_arguments(&signature, &arguments, &keywords);
+        }
+        result
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expr,
+        _question: &Token,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> Self::Output {
+        self.check_expr(condition);
+        let then_type = self.check_expr(then_branch);
+        let else_type = self.check_expr(else_branch);
+        join(&then_type, &else_type)
+    }
+
+    fn visit_get(&mut self, object: &Expr, _name: &Token) -> Self::Output {
+        self.check_expr(object);
+        Type::Any
+    }
+
+    fn visit_set(
+        &mut self,
+        object: &Expr,
+        _name: &Token,
+        operator: Option<&Token>,
+        value: &Expr,
+    ) -> Self::Output {
+        self.check_expr(object);
+        let value = self.check_expr(value);
+        match operator {
+            Some(_) => Type::Any,
+            None => value,
+        }
+    }
+
+    fn visit_super(&mut self, _id: ExprId, _keyword: &Token, _method: &Token) -> Self::Output {
+        Type::Any
+    }
+
+    fn visit_this(&mut self, _id: ExprId, _keyword: &Token) -> Self::Output {
+        match &self.current_class {
+            Some(class) => Type::Instance(class.clone()),
+            None => Type::Any,
+        }
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> Self::Output {
+        self.check_expr(expression)
+    }
+
+    fn visit_literal(&mut self, _token: Option<&Token>, value: &TokenLiteral) -> Self::Output {
+        match value {
+            TokenLiteral::Nil => Type::Nil,
+            TokenLiteral::String(_) => Type::String,
+            TokenLiteral::Number(_) | TokenLiteral::Integer(_) | TokenLiteral::BigInt(_) => {
+                Type::Number
+            }
+            TokenLiteral::Bool(_) => Type::Bool,
+        }
+    }
+
+    fn visit_logical(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Self::Output {
+        let left = self.check_expr(left);
+        let right = self.check_expr(right);
+        join(&left, &right)
+    }
+
+    fn visit_tuple(&mut self, elements: &[Expr]) -> Self::Output {
+        for element in elements {
+            self.check_expr(element);
+        }
+        Type::Tuple
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Self::Output {
+        let right = self.check_expr(right);
+        match operator.ttype {
+            TokenType::Bang => Type::Bool,
+            _ if is_number(&right) => Type::Number,
+            _ => Type::Any,
+        }
+    }
+
+    fn visit_variable(&mut self, _id: ExprId, name: &Token) -> Self::Output {
+        self.look_up(name)
+            .map_or(Type::Any, |variable| variable.ty.clone())
+    }
+
+    fn visit_yield(&mut self, _keyword: &Token, value: Option<&Expr>) -> Self::Output {
+        if let Some(value) = value {
+            self.check_expr(value);
+        }
+        Type::Any
+    }
+}
@@ -1,23 +1,43 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
+use num_bigint_dig::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
 use ordered_float::OrderedFloat;
 
 use crate::{
-    lox_callable::LoxCallable, lox_class::LoxClass, lox_function::LoxFunction,
-    lox_instance::LoxInstance, token::TokenLiteral,
+    gc::{self, Trace},
+    lox_callable::LoxCallable,
+    lox_class::LoxClass,
+    lox_function::LoxFunction,
+    lox_generator::LoxGenerator,
+    lox_instance::LoxInstance,
+    lox_trait::LoxTrait,
+    token::TokenLiteral,
 };
 
 /// Value of an evaluated expression
 #[derive(Clone)]
 pub enum Value {
     Nil,
-    String(String),
+    String(Rc<str>),
     Number(OrderedFloat<f64>),
+    Integer(i64),
+    BigInt(Rc<BigInt>),
     Bool(bool),
     NativeFn(&'static dyn LoxCallable),
+    /// A native method bound to the object it was looked up on.
+    NativeMethod(Rc<dyn LoxCallable>),
     LoxFn(LoxFunction),
     LoxClass(LoxClass),
+    LoxTrait(LoxTrait),
     LoxInstance(Rc<RefCell<LoxInstance>>),
+    Generator(Rc<RefCell<LoxGenerator>>),
+    /// An immutable sequence, such as the values of `return a, b;`.
+    Tuple(Rc<[Value]>),
 }
 
 impl From<TokenLiteral> for Value {
@@ -26,6 +46,8 @@ impl From<TokenLiteral> for Value {
             TokenLiteral::Nil => Self::Nil,
             TokenLiteral::String(v) => Self::String(v),
             TokenLiteral::Number(v) => Self::Number(v),
+            TokenLiteral::Integer(v) => Self::Integer(v),
+            TokenLiteral::BigInt(v) => Self::BigInt(v),
             TokenLiteral::Bool(v) => Self::Bool(v),
         }
     }
@@ -33,7 +55,7 @@ impl From<TokenLiteral> for Value {
 
 impl From<String> for Value {
     fn from(value: String) -> Self {
-        Self::String(value)
+        Self::String(value.into())
     }
 }
 
@@ -43,6 +65,18 @@ impl From<OrderedFloat<f64>> for Value {
     }
 }
 
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(value: BigInt) -> Self {
+        Self::BigInt(Rc::new(value))
+    }
+}
+
 impl From<bool> for Value {
     fn from(value: bool) -> Self {
         Self::Bool(value)
@@ -61,9 +95,21 @@ impl From<LoxClass> for Value {
     }
 }
 
+impl From<LoxTrait> for Value {
+    fn from(value: LoxTrait) -> Self {
+        Self::LoxTrait(value)
+    }
+}
+
 impl From<LoxInstance> for Value {
     fn from(value: LoxInstance) -> Self {
-        Self::LoxInstance(Rc::new(RefCell::new(value)))
+        Self::LoxInstance(gc::alloc_instance(value))
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Self {
+        Self::Tuple(gc::alloc_tuple(elements))
     }
 }
 
@@ -73,6 +119,22 @@ impl From<Rc<RefCell<LoxInstance>>> for Value {
     }
 }
 
+/// The integer a float is exactly equal to, if it is integral and fits in an `i64`.
+fn exact_integer(n: f64) -> Option<i64> {
+    // 2^63 is exactly representable, unlike i64::MAX
+    let in_range = (-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&n);
+    (in_range && n.fract() == 0.0).then_some(n as i64)
+}
+
+/// The big integer a float is exactly equal to, if it is integral.
+fn exact_big_integer(n: f64) -> Option<BigInt> {
+    if n.is_finite() && n.fract() == 0.0 {
+        BigInt::from_f64(n)
+    } else {
+        None
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -80,9 +142,141 @@ impl PartialEq for Value {
             (Self::Nil, _) => false,
             (Self::String(l0), Self::String(r0)) => l0 == r0,
             (Self::Number(l0), Self::Number(r0)) => l0 == r0,
+            (Self::Integer(l0), Self::Integer(r0)) => l0 == r0,
+            (Self::Integer(l0), Self::Number(r0)) | (Self::Number(r0), Self::Integer(l0)) => {
+                exact_integer(**r0) == Some(*l0)
+            }
+            (Self::BigInt(l0), Self::BigInt(r0)) => l0 == r0,
+            (Self::BigInt(l0), Self::Integer(r0)) | (Self::Integer(r0), Self::BigInt(l0)) => {
+                **l0 == BigInt::from(*r0)
+            }
+            (Self::BigInt(l0), Self::Number(r0)) | (Self::Number(r0), Self::BigInt(l0)) => {
+                exact_big_integer(**r0).as_ref() == Some(&**l0)
+            }
             (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
             (Self::LoxClass(l0), Self::LoxClass(r0)) => l0 == r0,
+            (Self::LoxTrait(l0), Self::LoxTrait(r0)) => l0 == r0,
+            (Self::LoxFn(l0), Self::LoxFn(r0)) => l0 == r0,
+            (Self::NativeFn(l0), Self::NativeFn(r0)) => l0.name() == r0.name(),
+            (Self::NativeMethod(l0), Self::NativeMethod(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::LoxInstance(l0), Self::LoxInstance(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Generator(l0), Self::Generator(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Tuple(l0), Self::Tuple(r0)) => l0 == r0,
             _ => false,
         }
     }
 }
+
+impl Eq for Value {}
+
+/// Consistent with `eq`: numbers that compare equal hash the same whatever their kind,
+/// instances, functions, classes and traits hash by identity, and tuples by their elements.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Nil => {}
+            Self::String(s) => s.hash(state),
+            // integral numbers hash as the integer they equal, so every kind agrees
+            Self::Number(n) => match (exact_integer(**n), exact_big_integer(**n)) {
+                (Some(n), _) => n.hash(state),
+                (None, Some(n)) => n.hash(state),
+                (None, None) => n.hash(state),
+            },
+            Self::Integer(n) => n.hash(state),
+            Self::BigInt(n) => match n.to_i64() {
+                Some(n) => n.hash(state),
+                None => n.hash(state),
+            },
+            Self::Bool(b) => b.hash(state),
+            Self::NativeFn(f) => f.name().hash(state),
+            Self::LoxFn(f) => f.hash(state),
+            Self::LoxClass(c) => c.hash(state),
+            Self::LoxTrait(t) => t.hash(state),
+            Self::NativeMethod(f) => (Rc::as_ptr(f) as *const ()).hash(state),
+            Self::LoxInstance(i) => Rc::as_ptr(i).hash(state),
+            Self::Generator(g) => Rc::as_ptr(g).hash(state),
+            Self::Tuple(elements) => elements.hash(state),
+        }
+    }
+}
+
+impl Trace for Value {
+    fn trace(&self, refs: &mut Vec<*const ()>) {
+        match self {
+            Self::LoxFn(function) => function.trace(refs),
+            Self::LoxClass(class) => class.trace(refs),
+            Self::LoxTrait(used) => used.trace(refs),
+            Self::LoxInstance(instance) => refs.push(Rc::as_ptr(instance) as *const ()),
+            Self::Generator(generator) => refs.push(Rc::as_ptr(generator) as *const ()),
+            Self::Tuple(elements) => refs.push(Rc::as_ptr(elements) as *const ()),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
+
+    use super::*;
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_equal_numbers_hash_the_same() {
+        let numbers = [
+            Value::Integer(3),
+            Value::Number(OrderedFloat(3.0)),
+            Value::from(BigInt::from(3)),
+        ];
+        for a in &numbers {
+            for b in &numbers {
+                assert!(a == b);
+                assert_eq!(hash(a), hash(b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_integers_and_floats_compare_exactly() {
+        // 2^53 + 1 has no float representation, so it is not the float next to it
+        let integer = Value::Integer(9_007_199_254_740_993);
+        let float = Value::Number(OrderedFloat(9_007_199_254_740_992.0));
+        assert!(integer != float);
+        assert!(Value::Integer(9_007_199_254_740_992) == float);
+        assert!(Value::Integer(2) != Value::Number(OrderedFloat(2.5)));
+        assert!(Value::Integer(i64::MAX) != Value::Number(OrderedFloat(9.223372036854776e18)));
+
+        let big = Value::from(BigInt::from(i64::MAX) * 4 + 1);
+        let big_float = Value::Number(OrderedFloat(3.6893488147419103e19));
+        assert!(big != big_float);
+        assert!(Value::from(BigInt::from(i64::MAX) * 4 + 4) == big_float);
+        assert_eq!(
+            hash(&Value::from(BigInt::from(i64::MAX) * 4 + 4)),
+            hash(&big_float)
+        );
+    }
+
+    #[test]
+    // instances hash by address, never by their mutable fields
+    #[allow(clippy::mutable_key_type)]
+    fn test_instances_are_equal_by_identity() {
+        let class = LoxClass::new("Point", None, Vec::new(), HashMap::new());
+        let a = Value::from(LoxInstance::new(&class));
+        let b = Value::from(LoxInstance::new(&class));
+        assert!(a == a.clone());
+        assert!(a != b);
+
+        let set: HashSet<Value> = [a.clone(), b.clone(), a.clone()].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&a));
+
+        let other = LoxClass::new("Point", None, Vec::new(), HashMap::new());
+        assert!(Value::from(class.clone()) == Value::from(class.clone()));
+        assert!(Value::from(class) != Value::from(other));
+    }
+}
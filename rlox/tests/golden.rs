@@ -0,0 +1,34 @@
+use std::{env, fs, process};
+
+use rlox::test_runner::TestRunner;
+
+fn runner() -> TestRunner {
+    TestRunner::with_interpreter(env!("CARGO_BIN_EXE_rlox"))
+}
+
+#[test]
+fn test_golden_scripts() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../test");
+    assert!(runner().run(dir), "golden scripts failed, see the output above");
+}
+
+#[test]
+fn test_failing_script() {
+    let dir = env::temp_dir().join(format!("rlox-golden-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let cases = [
+        ("output.lox", "print 1; // expect: 2\n"),
+        ("exit_code.lox", "print 1; // expect: 1\nnil();\n"),
+        (
+            "runtime_error.lox",
+            "nil(); // expect runtime error: Expected some other error.\n",
+        ),
+    ];
+    for (name, source) in cases {
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        let passed = runner().run(path.to_str().unwrap());
+        assert!(!passed, "{} should have failed", name);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}